@@ -1,8 +1,7 @@
 #!/bin/bash
 
 ./target/release/day01  <inputs/day01.txt
-./target/release/day02a <inputs/day02.txt
-./target/release/day02b <inputs/day02.txt
+./target/release/day02  <inputs/day02.txt
 ./target/release/day03  <inputs/day03.txt
 ./target/release/day04  <inputs/day04.txt
 ./target/release/day05  <inputs/day05.txt
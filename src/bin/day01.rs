@@ -0,0 +1,54 @@
+use std::env;
+
+use color_eyre::eyre::{eyre, Result};
+
+use aoc21::day01::{parse_input, Cleaner, SonarReport, Sweep};
+
+fn main() -> Result<()> {
+    let mut running = false;
+    let mut report = None;
+    let mut filters = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &*arg {
+            // Print the totals and window mean after every reading, for when the input is a live feed
+            "--running" => running = true,
+            "--report" => report = Some(SonarReport::new(3)),
+            "--filter" => filters.push(
+                args.next()
+                    .ok_or_else(|| eyre!("--filter requires a value"))?
+                    .parse()?,
+            ),
+            _ => return Err(eyre!("unknown argument: {}", arg)),
+        }
+    }
+
+    let mut cleaner = Cleaner::new(&filters);
+    let mut one = Sweep::new(1);
+    let mut two = Sweep::new(3);
+    for depth in parse_input().filter_map(|d| cleaner.push(d)) {
+        let (p1, p2) = (one.push(depth), two.push(depth));
+        if running {
+            match two.mean() {
+                Some(mean) => {
+                    println!("{}: part 1: {} part 2: {} mean: {:.1}", depth, p1, p2, mean)
+                }
+                None => println!("{}: part 1: {} part 2: {}", depth, p1, p2),
+            }
+        }
+        if let Some(r) = &mut report {
+            r.push(depth);
+        }
+    }
+
+    for flag in &cleaner.flagged {
+        println!("{}", flag);
+    }
+    println!("part 1: {}", one.increases());
+    println!("part 2: {}", two.increases());
+    if let Some(r) = report {
+        println!("{}", r);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,120 @@
+use std::{env, io};
+
+use color_eyre::eyre::{eyre, Result};
+
+use aoc21::day02::{
+    normalize, parse_input, write_csv, Aim, Directions, DiveStats, Envelope, Location, Plain, Plan,
+    Steering, Trajectory,
+};
+
+fn main() -> Result<()> {
+    let models: [(&str, &dyn Steering); 2] = [("part 1", &Plain), ("part 2", &Aim)];
+
+    let mut stats = false;
+    let mut normalized = false;
+    // Only check the manoeuvres are safe when asked, the puzzle doesn't care
+    let mut envelope: Option<Envelope> = None;
+    let mut csv = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--stats" => stats = true,
+            // Print the directions back out with runs merged and zero moves dropped
+            "--normalize" => normalized = true,
+            // Dump the trajectory of one model instead, for plotting the dive profile
+            "--csv" => {
+                csv = match args.next().as_deref() {
+                    Some("plain") => Some(models[0].1),
+                    Some("aim") => Some(models[1].1),
+                    _ => return Err(eyre!("--csv requires either plain or aim")),
+                }
+            }
+            "--validate" => envelope = Some(envelope.unwrap_or_default()),
+            "--min-depth" | "--max-depth" | "--max-aim" => {
+                let n = args
+                    .next()
+                    .ok_or_else(|| eyre!("{} requires a number", arg))?
+                    .parse()?;
+                let env = envelope.get_or_insert_with(Envelope::default);
+                match &*arg {
+                    "--min-depth" => env.min_depth = n,
+                    "--max-depth" => env.max_depth = n,
+                    _ => env.max_aim = n,
+                }
+            }
+            // Work out the directions that reach a location, e.g. "horiz 15 depth 60 aim 10"
+            "--plan" => {
+                let target: Location = args
+                    .next()
+                    .ok_or_else(|| eyre!("--plan requires a location"))?
+                    .parse()?;
+                let planners: [(&str, &dyn Plan); 2] = [("plain", &Plain), ("aim", &Aim)];
+                for (name, planner) in planners {
+                    let dirs = planner
+                        .plan(target)
+                        .ok_or_else(|| eyre!("{} is out of reach", target))?;
+                    let dirs: Vec<_> = dirs.iter().map(|d| d.to_string()).collect();
+                    println!("{}: {}", name, dirs.join(", "));
+                }
+                return Ok(());
+            }
+            _ => return Err(eyre!("unknown argument: {}", arg)),
+        }
+    }
+
+    // Repeats are expanded as they run, so a script never has to fit in memory flattened
+    let script = parse_input()?;
+    let dirs = || Directions::new(&script);
+    if normalized {
+        for d in normalize(dirs()) {
+            println!("{}", d);
+        }
+        return Ok(());
+    }
+    if let Some(model) = csv {
+        let mut trajectory = Trajectory::new(model, dirs());
+        write_csv(io::stdout().lock(), &mut trajectory)?;
+        return match trajectory.overflow {
+            Some((dir, loc)) => Err(eyre!("{} overflows at {}", dir, loc)),
+            None => Ok(()),
+        };
+    }
+
+    for (name, model) in models {
+        let loc = match envelope {
+            Some(env) => match env.validate(model, dirs()) {
+                Ok(loc) => loc,
+                Err(breach) => {
+                    println!("{}: unsafe, {}", name, breach);
+                    continue;
+                }
+            },
+            None => model
+                .execute(Location::default(), &script)
+                .ok_or_else(|| eyre!("{}: the directions overflow", name))?,
+        };
+        let Location { horiz, depth, .. } = loc;
+        let product = match horiz.checked_mul(depth) {
+            Some(product) => product,
+            None if envelope.is_some() => {
+                println!("{}: unsafe, horiz * depth overflows at {}", name, loc);
+                continue;
+            }
+            None => return Err(eyre!("{}: horiz * depth overflows at {}", name, loc)),
+        };
+        println!("{}: {}", name, product);
+        if stats {
+            let DiveStats {
+                max_depth,
+                distance,
+                time_underwater,
+                directions,
+            } = DiveStats::new(Trajectory::new(model, dirs()));
+            println!(
+                "    max depth {}, distance {:.1}, underwater for {} of {} directions",
+                max_depth, distance, time_underwater, directions
+            );
+        }
+    }
+    Ok(())
+}
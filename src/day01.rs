@@ -1,35 +1,375 @@
-use std::io::{self, BufRead};
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, BufRead},
+    str::FromStr,
+};
 
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{eyre, Result};
 
-fn parse_input() -> Vec<usize> {
+pub fn parse_input() -> impl Iterator<Item = u64> {
     io::stdin()
         .lock()
         .lines()
         .map(|line| line.unwrap().parse().unwrap())
-        .collect()
 }
 
-fn run_part_one(input: &[usize]) -> usize {
-    input.windows(2).filter(|win| win[0] < win[1]).count()
+// Counts how often the sum of a sliding window increases over a stream of readings.
+// Consecutive windows share all but their first and last readings, so only the reading
+// leaving needs comparing against the one entering, and only the last `width` readings are
+// kept in a ring buffer.
+#[derive(Debug)]
+pub struct Sweep {
+    window: Box<[u64]>,
+    // Index of the oldest reading, which is the next to leave the window
+    next: usize,
+    full: bool,
+    // Wide enough that no window of u64 readings can overflow it
+    sum: u128,
+    increases: usize,
 }
 
-fn run_part_two(input: &[usize]) -> usize {
-    run_part_one(
-        &input
-            .windows(3)
-            .map(|s| s[0] + s[1] + s[2])
-            .collect::<Vec<_>>(),
-    )
+impl Sweep {
+    pub fn new(width: usize) -> Self {
+        assert!(width > 0, "window width must be at least 1");
+        Self {
+            window: vec![0; width].into_boxed_slice(),
+            next: 0,
+            full: false,
+            sum: 0,
+            increases: 0,
+        }
+    }
+
+    // Returns the running total of increases
+    pub fn push(&mut self, depth: u64) -> usize {
+        let leaving = self.window[self.next];
+        if self.full && leaving < depth {
+            self.increases += 1;
+        }
+        self.sum = self.sum - leaving as u128 + depth as u128;
+        self.window[self.next] = depth;
+        self.next = (self.next + 1) % self.window.len();
+        self.full |= self.next == 0;
+        self.increases
+    }
+
+    pub fn increases(&self) -> usize {
+        self.increases
+    }
+
+    // Mean of the readings in the window, once it's full
+    pub fn mean(&self) -> Option<f64> {
+        self.full
+            .then(|| self.sum as f64 / self.window.len() as f64)
+    }
+}
+
+// How often the sum of a sliding window of `window` readings increases. An empty window never
+// changes, so that's 0. This needs a ring buffer of `window` readings, see `count_increases_in`
+// for readings that are already in memory
+pub fn count_increases(input: impl IntoIterator<Item = u64>, window: usize) -> usize {
+    if window == 0 {
+        return 0;
+    }
+    let mut sweep = Sweep::new(window);
+    for depth in input {
+        sweep.push(depth);
+    }
+    sweep.increases()
+}
+
+// Same as `count_increases` without allocating. Consecutive windows share all but their first
+// and last elements, so only the element leaving needs comparing against the one entering
+pub fn count_increases_in(input: &[u64], window: usize) -> usize {
+    input
+        .iter()
+        .zip(input.iter().skip(window))
+        .filter(|(leaving, entering)| leaving < entering)
+        .count()
+}
+
+// Statistics over a whole sonar sweep, built up one reading at a time
+#[derive(Debug)]
+pub struct SonarReport {
+    pub readings: usize,
+    pub increases: usize,
+    pub decreases: usize,
+    // Consecutive readings at the same depth
+    pub plateaus: usize,
+    // Start index and length of the longest strictly increasing run of readings
+    pub longest_rise: (usize, usize),
+    pub max_depth: Option<u64>,
+    pub min_depth: Option<u64>,
+    // Mean of the latest full window, the per-window means are only printed with --running
+    pub rolling_mean: Option<f64>,
+    // Index of the reading after the largest fall between consecutive readings, and the fall
+    pub largest_drop: Option<(usize, u64)>,
+    prev: Option<u64>,
+    rise_start: usize,
+    window: Sweep,
+}
+
+impl SonarReport {
+    pub fn new(width: usize) -> Self {
+        Self {
+            readings: 0,
+            increases: 0,
+            decreases: 0,
+            plateaus: 0,
+            longest_rise: (0, 0),
+            max_depth: None,
+            min_depth: None,
+            rolling_mean: None,
+            largest_drop: None,
+            prev: None,
+            rise_start: 0,
+            window: Sweep::new(width),
+        }
+    }
+
+    pub fn from_readings(input: impl IntoIterator<Item = u64>, width: usize) -> Self {
+        let mut report = Self::new(width);
+        input.into_iter().for_each(|depth| report.push(depth));
+        report
+    }
+
+    pub fn push(&mut self, depth: u64) {
+        let i = self.readings;
+        match self.prev {
+            Some(prev) if prev < depth => self.increases += 1,
+            Some(prev) if prev > depth => {
+                self.decreases += 1;
+                self.rise_start = i;
+                if self
+                    .largest_drop
+                    .is_none_or(|(_, drop)| prev - depth > drop)
+                {
+                    self.largest_drop = Some((i, prev - depth));
+                }
+            }
+            Some(_) => {
+                self.plateaus += 1;
+                self.rise_start = i;
+            }
+            None => (),
+        }
+        if i + 1 - self.rise_start > self.longest_rise.1 {
+            self.longest_rise = (self.rise_start, i + 1 - self.rise_start);
+        }
+
+        self.max_depth = Some(self.max_depth.map_or(depth, |d| d.max(depth)));
+        self.min_depth = Some(self.min_depth.map_or(depth, |d| d.min(depth)));
+        self.window.push(depth);
+        self.rolling_mean = self.window.mean();
+        self.prev = Some(depth);
+        self.readings += 1;
+    }
+}
+
+impl fmt::Display for SonarReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let opt = |d: Option<u64>| d.map_or_else(|| "-".to_string(), |d| d.to_string());
+        writeln!(f, "readings:     {}", self.readings)?;
+        writeln!(f, "increases:    {}", self.increases)?;
+        writeln!(f, "decreases:    {}", self.decreases)?;
+        writeln!(f, "plateaus:     {}", self.plateaus)?;
+        writeln!(
+            f,
+            "longest rise: {} readings from index {}",
+            self.longest_rise.1, self.longest_rise.0
+        )?;
+        writeln!(f, "max depth:    {}", opt(self.max_depth))?;
+        writeln!(f, "min depth:    {}", opt(self.min_depth))?;
+        match self.largest_drop {
+            Some((i, drop)) => writeln!(f, "largest drop: {} at index {}", drop, i)?,
+            None => writeln!(f, "largest drop: -")?,
+        }
+        match self.rolling_mean {
+            Some(mean) => write!(f, "rolling mean: {:.1}", mean),
+            None => write!(f, "rolling mean: -"),
+        }
+    }
+}
+
+// A cleaning stage for glitchy readings, applied before anything is counted
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Filter {
+    // Replaces each reading with the median of it and the readings before it, `n` in all
+    RollingMedian(usize),
+    // Drops readings more than `threshold` standard deviations from the mean of the last
+    // `window` readings that were kept. A run of `window` dropped readings is taken as a change
+    // of level, and becomes the history the next readings are compared against
+    ZScore { window: usize, threshold: f64 },
+    // Drops readings more than this far from the last reading that was kept, unless they're
+    // within it of the reading just dropped, as two in a row means the depth really changed
+    MaxJump(u64),
+}
+
+// Written as `median:N`, `zscore:WINDOW:THRESHOLD` or `jump:N`
+impl FromStr for Filter {
+    type Err = color_eyre::eyre::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<_> = s.split(':').collect();
+        match parts[..] {
+            ["median", n] => match n.parse()? {
+                0 => Err(eyre!("median window must be at least 1")),
+                n => Ok(Filter::RollingMedian(n)),
+            },
+            ["zscore", window, threshold] => match (window.parse()?, threshold.parse()?) {
+                (window, _) if window < 2 => Err(eyre!("zscore window must be at least 2")),
+                (_, threshold) if !f64::is_finite(threshold) || threshold < 0.0 => {
+                    Err(eyre!("zscore threshold must be finite and at least 0"))
+                }
+                (window, threshold) => Ok(Filter::ZScore { window, threshold }),
+            },
+            ["jump", n] => Ok(Filter::MaxJump(n.parse()?)),
+            _ => Err(eyre!("invalid filter: {}", s)),
+        }
+    }
+}
+
+// A reading one of the filters changed or dropped
+#[derive(Debug, PartialEq, Clone)]
+pub struct Flag {
+    pub index: usize,
+    pub depth: u64,
+    pub filter: Filter,
+    // What a rolling median replaced it with, None if it was dropped
+    pub replaced: Option<u64>,
+}
+
+impl fmt::Display for Flag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.replaced {
+            Some(d) => write!(
+                f,
+                "reading {} ({}) replaced with {} by {:?}",
+                self.index, self.depth, d, self.filter
+            ),
+            None => write!(
+                f,
+                "reading {} ({}) dropped by {:?}",
+                self.index, self.depth, self.filter
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Stage {
+    filter: Filter,
+    // Raw readings for a rolling median, kept readings for the others
+    history: VecDeque<u64>,
+    // Readings dropped since the last one that was kept
+    rejected: Vec<u64>,
 }
 
-fn main() -> Result<()> {
-    let input = parse_input();
+impl Stage {
+    // The reading to pass on to the next stage, or None to drop it
+    fn apply(&mut self, depth: u64) -> Option<u64> {
+        match self.filter {
+            Filter::RollingMedian(n) => {
+                if self.history.len() == n {
+                    self.history.pop_front();
+                }
+                self.history.push_back(depth);
+                let mut sorted: Vec<_> = self.history.iter().copied().collect();
+                sorted.sort_unstable();
+                Some(sorted[(sorted.len() - 1) / 2])
+            }
+            Filter::ZScore { window, threshold } => {
+                let n = self.history.len() as f64;
+                // Summed as u128 so huge readings can't overflow, like `Sweep`
+                let mean = self.history.iter().map(|&d| d as u128).sum::<u128>() as f64 / n;
+                let var = self
+                    .history
+                    .iter()
+                    .map(|&d| (d as f64 - mean).powi(2))
+                    .sum::<f64>()
+                    / n;
+                // Too little history, or a perfectly flat one, says nothing about outliers
+                if self.history.len() >= 2
+                    && var > 0.0
+                    && (depth as f64 - mean).abs() / var.sqrt() > threshold
+                {
+                    self.rejected.push(depth);
+                    if self.rejected.len() < window {
+                        return None;
+                    }
+                    self.history = self.rejected.drain(..).collect();
+                    return Some(depth);
+                }
+                self.rejected.clear();
+                if self.history.len() == window {
+                    self.history.pop_front();
+                }
+                self.history.push_back(depth);
+                Some(depth)
+            }
+            Filter::MaxJump(max) => {
+                let confirmed = self
+                    .rejected
+                    .last()
+                    .is_some_and(|r| r.abs_diff(depth) <= max);
+                if let Some(&last) = self.history.back() {
+                    if last.abs_diff(depth) > max && !confirmed {
+                        self.rejected.push(depth);
+                        return None;
+                    }
+                }
+                self.rejected.clear();
+                self.history.clear();
+                self.history.push_back(depth);
+                Some(depth)
+            }
+        }
+    }
+}
+
+// Runs each reading through the filters in order, remembering every reading they flagged
+#[derive(Debug)]
+pub struct Cleaner {
+    stages: Vec<Stage>,
+    index: usize,
+    pub flagged: Vec<Flag>,
+}
 
-    println!("part 1: {}", run_part_one(&input));
-    println!("part 2: {}", run_part_two(&input));
+impl Cleaner {
+    pub fn new(filters: &[Filter]) -> Self {
+        Self {
+            stages: filters
+                .iter()
+                .map(|&filter| Stage {
+                    filter,
+                    history: VecDeque::new(),
+                    rejected: Vec::new(),
+                })
+                .collect(),
+            index: 0,
+            flagged: Vec::new(),
+        }
+    }
 
-    Ok(())
+    // The cleaned reading, or None if it was dropped
+    pub fn push(&mut self, depth: u64) -> Option<u64> {
+        let index = self.index;
+        self.index += 1;
+        let mut depth = depth;
+        for stage in &mut self.stages {
+            let out = stage.apply(depth);
+            if out != Some(depth) {
+                self.flagged.push(Flag {
+                    index,
+                    depth,
+                    filter: stage.filter,
+                    replaced: out,
+                });
+            }
+            depth = out?;
+        }
+        Some(depth)
+    }
 }
 
 #[cfg(test)]
@@ -46,29 +386,153 @@ mod tests {
 260
 263";
 
+    fn parse_test_input() -> impl Iterator<Item = u64> {
+        INPUT.lines().map(|l| l.parse::<u64>().unwrap())
+    }
+
     #[test]
     fn part_one() {
+        assert_eq!(count_increases(parse_test_input(), 1), 7);
+    }
+
+    #[test]
+    fn part_two() {
+        assert_eq!(count_increases(parse_test_input(), 3), 5);
+    }
+
+    #[test]
+    fn running_totals() {
+        let mut sweep = Sweep::new(3);
+        let totals: Vec<_> = parse_test_input().map(|d| sweep.push(d)).collect();
+        assert_eq!(totals, [0, 0, 0, 1, 1, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn huge_readings() {
+        let mut sweep = Sweep::new(2);
+        for _ in 0..3 {
+            sweep.push(u64::MAX);
+        }
+        assert_eq!(sweep.increases(), 0);
+        assert_eq!(sweep.mean(), Some(u64::MAX as f64));
+    }
+
+    #[test]
+    fn huge_zscore_readings() {
+        let mut cleaner = Cleaner::new(&["zscore:3:2".parse().unwrap()]);
+        // Far enough apart to still differ as f64s
+        let huge = [u64::MAX, u64::MAX - 8192, u64::MAX - 4096, u64::MAX, 0];
+        let kept: Vec<_> = huge.iter().filter_map(|&d| cleaner.push(d)).collect();
+        assert_eq!(kept, &huge[..4]);
+        assert_eq!(cleaner.flagged.len(), 1);
+    }
+
+    #[test]
+    fn windows() {
+        // Deterministic noise so there are plenty of both increases and decreases
+        let mut seed = 42u64;
+        let input: Vec<u64> = (0..2000)
+            .map(|_| {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                (seed >> 33) % 1000
+            })
+            .collect();
+
+        for window in 1..=50 {
+            let sums: Vec<u64> = input.windows(window).map(|w| w.iter().sum()).collect();
+            let naive = sums.windows(2).filter(|w| w[0] < w[1]).count();
+            assert_eq!(
+                count_increases(input.iter().copied(), window),
+                naive,
+                "window {}",
+                window
+            );
+            assert_eq!(
+                count_increases_in(&input, window),
+                naive,
+                "window {}",
+                window
+            );
+        }
+        assert_eq!(count_increases(input.iter().copied(), input.len()), 0);
+        assert_eq!(count_increases(input.iter().copied(), 0), 0);
+        assert_eq!(count_increases_in(&input, 0), 0);
+    }
+
+    #[test]
+    fn report() {
+        let r = SonarReport::from_readings(parse_test_input().chain([263, 100]), 3);
+        assert_eq!(r.readings, 12);
+        assert_eq!((r.increases, r.decreases, r.plateaus), (7, 3, 1));
+        // Two runs of 4, the first wins
+        assert_eq!(r.longest_rise, (0, 4));
+        assert_eq!((r.min_depth, r.max_depth), (Some(100), Some(269)));
+        assert_eq!(r.largest_drop, Some((11, 163)));
+        assert_eq!(r.rolling_mean, Some(626.0 / 3.0));
+
+        let r = SonarReport::from_readings([], 3);
         assert_eq!(
-            run_part_one(
-                &INPUT
-                    .lines()
-                    .map(|l| l.parse::<usize>().unwrap())
-                    .collect::<Vec<usize>>()
-            ),
-            7
+            (r.longest_rise, r.max_depth, r.largest_drop),
+            ((0, 0), None, None)
         );
     }
 
     #[test]
-    fn part_two() {
+    fn filters() {
+        // A glitch at index 3 that would otherwise count as an increase
+        let glitchy = [100, 101, 102, 9000, 103, 104, 104];
+        let clean = |filters: &[Filter]| {
+            let mut cleaner = Cleaner::new(filters);
+            let kept: Vec<_> = glitchy.iter().filter_map(|&d| cleaner.push(d)).collect();
+            (kept, cleaner.flagged)
+        };
+
+        let (kept, flagged) = clean(&[Filter::MaxJump(50)]);
+        assert_eq!(count_increases(kept, 1), 4);
         assert_eq!(
-            run_part_two(
-                &INPUT
-                    .lines()
-                    .map(|l| l.parse::<usize>().unwrap())
-                    .collect::<Vec<usize>>()
-            ),
-            5
+            flagged,
+            [Flag {
+                index: 3,
+                depth: 9000,
+                filter: Filter::MaxJump(50),
+                replaced: None
+            }]
         );
+
+        let (kept, flagged) = clean(&["zscore:5:3".parse().unwrap()]);
+        assert_eq!(kept, [100, 101, 102, 103, 104, 104]);
+        assert_eq!(flagged.len(), 1);
+
+        let (kept, flagged) = clean(&[Filter::RollingMedian(3)]);
+        assert_eq!(kept, [100, 100, 101, 102, 103, 104, 104]);
+        assert_eq!(flagged[2].replaced, Some(102));
+
+        // Nothing to compare the first reading with, but it doesn't lock out the rest
+        let mut cleaner = Cleaner::new(&[Filter::MaxJump(50)]);
+        let kept: Vec<_> = [9000, 100, 101, 102, 103]
+            .into_iter()
+            .filter_map(|d| cleaner.push(d))
+            .collect();
+        assert_eq!(kept, [9000, 101, 102, 103]);
+        assert_eq!(cleaner.flagged.len(), 1);
+        assert_eq!(cleaner.flagged[0].index, 1);
+
+        // A step change is dropped until a full window of it, then followed
+        let mut cleaner = Cleaner::new(&["zscore:5:3".parse().unwrap()]);
+        let shifted = [100, 101, 102, 100, 101, 500, 501, 502, 503, 504, 505, 506];
+        let kept: Vec<_> = shifted.iter().filter_map(|&d| cleaner.push(d)).collect();
+        assert_eq!(kept, [100, 101, 102, 100, 101, 504, 505, 506]);
+
+        assert!("median:0".parse::<Filter>().is_err());
+        for bad in [
+            "zscore:0:3",
+            "zscore:1:3",
+            "zscore:5:-1",
+            "zscore:5:inf",
+            "zscore:5:NaN",
+        ] {
+            assert!(bad.parse::<Filter>().is_err(), "{}", bad);
+        }
+        assert!("jump".parse::<Filter>().is_err());
     }
 }
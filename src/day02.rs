@@ -1,40 +1,937 @@
 use std::{
-    io::{self, BufRead},
+    fmt,
+    io::{self, Read, Write},
+    iter, slice,
     str::FromStr,
 };
 
 use color_eyre::eyre::{eyre, Result};
 
-pub fn parse_input() -> Vec<Direction> {
-    io::stdin()
-        .lock()
-        .lines()
-        .map(|line| line.unwrap().parse().unwrap())
-        .collect()
+// Reads a whole manoeuvre script, run it with `Directions` or `Steering::execute` so that
+// repeats are never expanded all at once
+pub fn parse_input() -> Result<Vec<Command>> {
+    let mut s = String::new();
+    io::stdin().lock().read_to_string(&mut s)?;
+    parse_script(&s)
 }
 
-#[derive(Debug, Default, PartialEq)]
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
 pub struct Location {
     pub horiz: i64,
     pub depth: i64,
     pub aim: i64,
 }
 
+#[derive(Debug, PartialEq, Copy, Clone)]
 pub enum Direction {
     Forward(i64),
     Down(i64),
     Up(i64),
+    Back(i64),
+    SetAim(i64),
+}
+
+impl Direction {
+    fn new(name: &str, n: &str) -> Result<Self> {
+        let parse = || {
+            n.parse()
+                .map_err(|e| eyre!("invalid number for {}: {}: {}", name, n, e))
+        };
+        match name {
+            "forward" => Ok(Direction::Forward(parse()?)),
+            "down" => Ok(Direction::Down(parse()?)),
+            "up" => Ok(Direction::Up(parse()?)),
+            "back" => Ok(Direction::Back(parse()?)),
+            "set-aim" => Ok(Direction::SetAim(parse()?)),
+            _ => Err(eyre!("invalid direction: {}", name)),
+        }
+    }
 }
 
 impl FromStr for Direction {
     type Err = color_eyre::eyre::Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut parts = s.split_ascii_whitespace();
-        match parts.next() {
-            Some("forward") => Ok(Direction::Forward(parts.next().unwrap().parse()?)),
-            Some("down") => Ok(Direction::Down(parts.next().unwrap().parse()?)),
-            Some("up") => Ok(Direction::Up(parts.next().unwrap().parse()?)),
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(name), Some(n), None) => Direction::new(name, n),
             _ => Err(eyre!("invalid direction: {}", s)),
         }
     }
 }
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Direction::Forward(i) => write!(f, "forward {}", i),
+            Direction::Down(i) => write!(f, "down {}", i),
+            Direction::Up(i) => write!(f, "up {}", i),
+            Direction::Back(i) => write!(f, "back {}", i),
+            Direction::SetAim(i) => write!(f, "set-aim {}", i),
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "horiz {} depth {} aim {}",
+            self.horiz, self.depth, self.aim
+        )
+    }
+}
+
+impl FromStr for Location {
+    type Err = color_eyre::eyre::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_ascii_whitespace().collect::<Vec<_>>()[..] {
+            ["horiz", horiz, "depth", depth, "aim", aim] => Ok(Location {
+                horiz: horiz.parse()?,
+                depth: depth.parse()?,
+                aim: aim.parse()?,
+            }),
+            _ => Err(eyre!("invalid location: {}", s)),
+        }
+    }
+}
+
+// Merges runs of the same direction and drops moves of zero, which leaves the submarine in
+// the same place under any of the steering models
+pub fn normalize(dirs: impl IntoIterator<Item = Direction>) -> impl Iterator<Item = Direction> {
+    use Direction::*;
+    let mut dirs = dirs
+        .into_iter()
+        .filter(|d| !matches!(d, Forward(0) | Down(0) | Up(0) | Back(0)));
+    // The direction that didn't merge into the last one returned
+    let mut pending = None;
+    iter::from_fn(move || {
+        let mut out = pending.take().or_else(|| dirs.next())?;
+        for d in dirs.by_ref() {
+            let merged = match (out, d) {
+                (Forward(a), Forward(b)) => a.checked_add(b).map(Forward),
+                (Down(a), Down(b)) => a.checked_add(b).map(Down),
+                (Up(a), Up(b)) => a.checked_add(b).map(Up),
+                (Back(a), Back(b)) => a.checked_add(b).map(Back),
+                // Only the last aim set matters
+                (SetAim(_), SetAim(b)) => Some(SetAim(b)),
+                _ => None,
+            };
+            match merged {
+                Some(m) => out = m,
+                None => {
+                    pending = Some(d);
+                    break;
+                }
+            }
+        }
+        Some(out)
+    })
+}
+
+// A manoeuvre script, a list of directions with blocks that repeat
+#[derive(Debug, PartialEq, Clone)]
+pub enum Command {
+    Dir(Direction),
+    Repeat(u64, Vec<Command>),
+}
+
+// Parses a script, one direction per line with `#` comments, blank lines and
+// `repeat N { ... }` blocks that can be nested. Braces can share a line with the
+// direction inside them, e.g. `repeat 2 { forward 1 }`, but not with another one
+pub fn parse_script(s: &str) -> Result<Vec<Command>> {
+    let mut tokens = s
+        .lines()
+        .enumerate()
+        .flat_map(|(i, line)| {
+            let code = line.split('#').next().unwrap_or_default();
+            code.split_ascii_whitespace()
+                .flat_map(split_braces)
+                .map(move |t| (i + 1, t))
+        })
+        .peekable();
+
+    let script = parse_block(&mut tokens)?;
+    match tokens.next() {
+        Some((line, t)) => Err(eyre!("line {}: unexpected `{}`", line, t)),
+        None => Ok(script),
+    }
+}
+
+// Braces don't need to be separated by whitespace, e.g. `repeat 2 {forward 1}`
+fn split_braces(word: &str) -> impl Iterator<Item = &str> {
+    let mut rest = word;
+    iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let end = match rest.find(['{', '}']) {
+            Some(0) => 1,
+            Some(i) => i,
+            None => rest.len(),
+        };
+        let (t, r) = rest.split_at(end);
+        rest = r;
+        Some(t)
+    })
+}
+
+// Parses commands until the end of the script or an unmatched `}`, which is left for the caller
+fn parse_block<'a>(
+    tokens: &mut iter::Peekable<impl Iterator<Item = (usize, &'a str)>>,
+) -> Result<Vec<Command>> {
+    let mut block = Vec::new();
+    while let Some(&(line, t)) = tokens.peek() {
+        if t == "}" {
+            break;
+        }
+        tokens.next();
+        let n = match tokens.next() {
+            Some((l, n)) if l == line => n,
+            _ => return Err(eyre!("line {}: `{}` requires a number", line, t)),
+        };
+        if t != "repeat" {
+            block.push(Command::Dir(
+                Direction::new(t, n).map_err(|e| eyre!("line {}: {}", line, e))?,
+            ));
+            end_of_line(tokens, line)?;
+            continue;
+        }
+
+        let count = n
+            .parse()
+            .map_err(|e| eyre!("line {}: invalid repeat count `{}`: {}", line, n, e))?;
+        if tokens.next().map(|(_, t)| t) != Some("{") {
+            return Err(eyre!("line {}: expected `{{` after `repeat {}`", line, n));
+        }
+        let body = parse_block(tokens)?;
+        // The line the block ends on, so `repeat 2 { forward 1 } down 1` is caught too
+        let end = match tokens.next() {
+            Some((end, "}")) => end,
+            _ => return Err(eyre!("line {}: `repeat` block is never closed", line)),
+        };
+        block.push(Command::Repeat(count, body));
+        end_of_line(tokens, end)?;
+    }
+    Ok(block)
+}
+
+// Nothing but closing braces can follow a direction or a block on its line
+fn end_of_line<'a>(
+    tokens: &mut iter::Peekable<impl Iterator<Item = (usize, &'a str)>>,
+    line: usize,
+) -> Result<()> {
+    match tokens.peek() {
+        Some(&(l, t)) if l == line && t != "}" => Err(eyre!(
+            "line {}: `{}` should be on a line of its own",
+            line,
+            t
+        )),
+        _ => Ok(()),
+    }
+}
+
+// Every direction of a script in order, expanding repeats as it goes rather than all at once
+pub struct Directions<'a> {
+    // The blocks being run, innermost last, with how many more times each one repeats
+    stack: Vec<(&'a [Command], slice::Iter<'a, Command>, u64)>,
+}
+
+impl<'a> Directions<'a> {
+    pub fn new(script: &'a [Command]) -> Self {
+        Self {
+            stack: vec![(script, script.iter(), 0)],
+        }
+    }
+}
+
+impl Iterator for Directions<'_> {
+    type Item = Direction;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (block, cmds, left) = self.stack.last_mut()?;
+            match cmds.next() {
+                Some(Command::Dir(d)) => return Some(*d),
+                Some(Command::Repeat(n, body)) => {
+                    if *n > 0 && !body.is_empty() {
+                        self.stack.push((body, body.iter(), n - 1));
+                    }
+                }
+                None if *left > 0 => {
+                    *left -= 1;
+                    *cmds = block.iter();
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+// An interpretation of what each direction does to the submarine
+pub trait Steering {
+    // The location after a direction, or None if it overflows
+    fn checked_steer(&self, loc: Location, dir: &Direction) -> Option<Location>;
+
+    // None if any direction overflows
+    fn run(&self, dirs: &[Direction]) -> Option<Location> {
+        dirs.iter()
+            .try_fold(Location::default(), |loc, d| self.checked_steer(loc, d))
+    }
+
+    // Runs a script without expanding its repeats, None if any direction overflows
+    fn execute(&self, mut loc: Location, script: &[Command]) -> Option<Location> {
+        for cmd in script {
+            match cmd {
+                Command::Dir(d) => loc = self.checked_steer(loc, d)?,
+                Command::Repeat(n, body) => {
+                    for _ in 0..*n {
+                        loc = self.execute(loc, body)?;
+                    }
+                }
+            }
+        }
+        Some(loc)
+    }
+}
+
+// Up and down change the depth directly
+pub struct Plain;
+
+impl Steering for Plain {
+    fn checked_steer(&self, mut loc: Location, dir: &Direction) -> Option<Location> {
+        match *dir {
+            Direction::Up(i) => loc.depth = loc.depth.checked_sub(i)?,
+            Direction::Down(i) => loc.depth = loc.depth.checked_add(i)?,
+            Direction::Forward(i) => loc.horiz = loc.horiz.checked_add(i)?,
+            Direction::Back(i) => loc.horiz = loc.horiz.checked_sub(i)?,
+            Direction::SetAim(i) => loc.aim = i,
+        }
+        Some(loc)
+    }
+}
+
+// Up and down change the aim, and moving forward dives along it
+pub struct Aim;
+
+impl Steering for Aim {
+    fn checked_steer(&self, mut loc: Location, dir: &Direction) -> Option<Location> {
+        match *dir {
+            Direction::Up(i) => loc.aim = loc.aim.checked_sub(i)?,
+            Direction::Down(i) => loc.aim = loc.aim.checked_add(i)?,
+            Direction::Forward(i) => {
+                loc.horiz = loc.horiz.checked_add(i)?;
+                loc.depth = loc.depth.checked_add(loc.aim.checked_mul(i)?)?;
+            }
+            // Reverses along the current aim
+            Direction::Back(i) => {
+                loc.horiz = loc.horiz.checked_sub(i)?;
+                loc.depth = loc.depth.checked_sub(loc.aim.checked_mul(i)?)?;
+            }
+            Direction::SetAim(i) => loc.aim = i,
+        }
+        Some(loc)
+    }
+}
+
+// Limits the submarine has to stay within while manoeuvring
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Envelope {
+    pub min_depth: i64,
+    pub max_depth: i64,
+    // Furthest the aim can be from level, either up or down
+    pub max_aim: i64,
+}
+
+// By default the submarine just can't leave the water
+impl Default for Envelope {
+    fn default() -> Self {
+        Self {
+            min_depth: 0,
+            max_depth: i64::MAX,
+            max_aim: i64::MAX,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Violation {
+    Overflow,
+    TooShallow(i64),
+    TooDeep(i64),
+    AimTooSteep(i64),
+}
+
+// The first direction that took the submarine outside of its envelope
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct Breach {
+    pub index: usize,
+    pub dir: Direction,
+    // Where the submarine was before the direction
+    pub loc: Location,
+    pub violation: Violation,
+}
+
+impl fmt::Display for Breach {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "direction {} ({}) from {} ",
+            self.index, self.dir, self.loc
+        )?;
+        match self.violation {
+            Violation::Overflow => write!(f, "overflows"),
+            Violation::TooShallow(d) => write!(f, "rises to depth {}", d),
+            Violation::TooDeep(d) => write!(f, "dives to depth {}", d),
+            Violation::AimTooSteep(a) => write!(f, "aims at {}", a),
+        }
+    }
+}
+
+impl Envelope {
+    // Replays the directions with checked arithmetic, stopping at the first one that leaves
+    // the envelope
+    pub fn validate(
+        &self,
+        steering: &dyn Steering,
+        dirs: impl IntoIterator<Item = Direction>,
+    ) -> Result<Location, Breach> {
+        let mut loc = Location::default();
+        for (index, dir) in dirs.into_iter().enumerate() {
+            let breach = |violation| Breach {
+                index,
+                dir,
+                loc,
+                violation,
+            };
+            let next = steering
+                .checked_steer(loc, &dir)
+                .ok_or_else(|| breach(Violation::Overflow))?;
+            if next.depth < self.min_depth {
+                return Err(breach(Violation::TooShallow(next.depth)));
+            }
+            if next.depth > self.max_depth {
+                return Err(breach(Violation::TooDeep(next.depth)));
+            }
+            if next.aim.unsigned_abs() > self.max_aim.unsigned_abs() {
+                return Err(breach(Violation::AimTooSteep(next.aim)));
+            }
+            loc = next;
+        }
+        Ok(loc)
+    }
+}
+
+// Steering that can work backwards from where the submarine should end up to the fewest
+// directions that get it there, using only non-negative moves
+pub trait Plan: Steering {
+    // None if a direction needed would overflow
+    fn plan(&self, target: Location) -> Option<Vec<Direction>>;
+}
+
+// Forward or back, whichever covers `h`
+fn travel(h: i128) -> Option<Direction> {
+    let d = if h >= 0 {
+        Direction::Forward(h.try_into().ok()?)
+    } else {
+        Direction::Back((-h).try_into().ok()?)
+    };
+    Some(d)
+}
+
+fn set_aim(a: i128) -> Option<Direction> {
+    Some(Direction::SetAim(a.try_into().ok()?))
+}
+
+// Each part of the location has its own direction, so each one that isn't zero takes one
+impl Plan for Plain {
+    fn plan(&self, target: Location) -> Option<Vec<Direction>> {
+        let Location { horiz, depth, aim } = target;
+        let mut dirs = Vec::new();
+        if horiz != 0 {
+            dirs.push(travel(horiz.into())?);
+        }
+        if depth > 0 {
+            dirs.push(Direction::Down(depth));
+        } else if depth < 0 {
+            dirs.push(Direction::Up(depth.checked_neg()?));
+        }
+        if aim != 0 {
+            dirs.push(Direction::SetAim(aim));
+        }
+        Some(dirs)
+    }
+}
+
+// Depth only changes when travelling with some aim, and aim starts at zero. So a dive needs at
+// least one aim change before travelling, and if one stretch of travel can't cover the depth
+// at a whole number aim it takes two, one before and one after setting the aim.
+impl Plan for Aim {
+    fn plan(&self, target: Location) -> Option<Vec<Direction>> {
+        let (h, d, a) = (
+            i128::from(target.horiz),
+            i128::from(target.depth),
+            i128::from(target.aim),
+        );
+        let mut dirs = Vec::new();
+        if d == 0 {
+            // Travel before aiming, so the depth never changes
+            if h != 0 {
+                dirs.push(travel(h)?);
+            }
+            if a != 0 {
+                dirs.push(set_aim(a)?);
+            }
+        } else if h != 0 && d % h == 0 {
+            // A single stretch of travel at the aim that reaches the depth
+            dirs.push(set_aim(d / h)?);
+            dirs.push(travel(h)?);
+            if a != d / h {
+                dirs.push(set_aim(a)?);
+            }
+        } else if a != 0 && d % a == 0 {
+            // Travel level first, then the rest of the way at the final aim
+            dirs.push(travel(h - d / a)?);
+            dirs.push(set_aim(a)?);
+            dirs.push(travel(d / a)?);
+        } else {
+            // One step at an aim that makes up the difference, then the rest at the final aim
+            dirs.push(set_aim(d - a * (h - 1))?);
+            dirs.push(travel(1)?);
+            dirs.push(set_aim(a)?);
+            dirs.push(travel(h - 1)?);
+        }
+        debug_assert_eq!(self.run(&dirs), Some(target));
+        Some(dirs)
+    }
+}
+
+// Every location the submarine passes through, one after each direction. It stops early at a
+// direction that overflows
+pub struct Trajectory<'a, I> {
+    steering: &'a dyn Steering,
+    dirs: I,
+    loc: Location,
+    // The direction that overflowed and where it was steered from
+    pub overflow: Option<(Direction, Location)>,
+}
+
+impl<'a, I: Iterator<Item = Direction>> Trajectory<'a, I> {
+    pub fn new(steering: &'a dyn Steering, dirs: impl IntoIterator<IntoIter = I>) -> Self {
+        Self {
+            steering,
+            dirs: dirs.into_iter(),
+            loc: Location::default(),
+            overflow: None,
+        }
+    }
+}
+
+impl<I: Iterator<Item = Direction>> Iterator for Trajectory<'_, I> {
+    type Item = Location;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.overflow.is_some() {
+            return None;
+        }
+        let dir = self.dirs.next()?;
+        match self.steering.checked_steer(self.loc, &dir) {
+            Some(loc) => self.loc = loc,
+            None => self.overflow = Some((dir, self.loc)),
+        }
+        self.overflow.is_none().then_some(self.loc)
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct DiveStats {
+    pub max_depth: i64,
+    // Straight line distance between each location, starting from the surface
+    pub distance: f64,
+    // Number of directions after which the submarine was below the surface
+    pub time_underwater: usize,
+    pub directions: usize,
+}
+
+impl DiveStats {
+    pub fn new(trajectory: impl IntoIterator<Item = Location>) -> Self {
+        let mut stats = DiveStats::default();
+        let mut prev = Location::default();
+        for loc in trajectory {
+            stats.max_depth = stats.max_depth.max(loc.depth);
+            stats.distance +=
+                ((loc.horiz - prev.horiz) as f64).hypot((loc.depth - prev.depth) as f64);
+            if loc.depth > 0 {
+                stats.time_underwater += 1;
+            }
+            stats.directions += 1;
+            prev = loc;
+        }
+        stats
+    }
+}
+
+// Writes the starting location followed by every location of the trajectory
+pub fn write_csv(
+    mut w: impl Write,
+    trajectory: impl IntoIterator<Item = Location>,
+) -> io::Result<()> {
+    writeln!(w, "step,horiz,depth,aim")?;
+    let start = iter::once(Location::default());
+    for (step, loc) in start.chain(trajectory).enumerate() {
+        writeln!(w, "{},{},{},{}", step, loc.horiz, loc.depth, loc.aim)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const INPUT: &str = "\
+forward 5
+down 5
+forward 8
+up 3
+down 8
+forward 2
+";
+
+    fn parse_test_input() -> Vec<Direction> {
+        INPUT
+            .lines()
+            .map(|l| l.parse::<Direction>().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn part_one() {
+        assert_eq!(
+            Plain.run(&parse_test_input()).unwrap(),
+            Location {
+                horiz: 15,
+                depth: 10,
+                aim: 0
+            }
+        );
+    }
+
+    #[test]
+    fn part_two() {
+        assert_eq!(
+            Aim.run(&parse_test_input()).unwrap(),
+            Location {
+                horiz: 15,
+                depth: 60,
+                aim: 10
+            }
+        );
+    }
+
+    #[test]
+    fn trajectory() {
+        let dirs = parse_test_input();
+        let depths: Vec<_> = Trajectory::new(&Aim, dirs.iter().copied())
+            .map(|l| l.depth)
+            .collect();
+        assert_eq!(depths, [0, 0, 40, 40, 40, 60]);
+        assert_eq!(
+            Trajectory::new(&Aim, dirs.iter().copied()).last(),
+            Aim.run(&dirs)
+        );
+
+        let over = [
+            Direction::Forward(i64::MAX),
+            Direction::Forward(1),
+            Direction::Up(1),
+        ];
+        let mut t = Trajectory::new(&Plain, over);
+        assert_eq!(t.by_ref().count(), 1);
+        assert_eq!(t.overflow.map(|(d, _)| d), Some(Direction::Forward(1)));
+        assert_eq!(Plain.run(&over), None);
+        let script = parse_script("forward 9223372036854775807\nrepeat 3 { forward 1 }").unwrap();
+        assert_eq!(Plain.execute(Location::default(), &script), None);
+
+        let stats = DiveStats::new(Trajectory::new(&Plain, dirs.iter().copied()));
+        assert_eq!(stats.max_depth, 10);
+        assert_eq!(stats.distance, 15.0 + 5.0 + 3.0 + 8.0);
+        assert_eq!((stats.time_underwater, stats.directions), (5, 6));
+    }
+
+    #[test]
+    fn csv() {
+        let dirs = parse_test_input();
+        let mut out = Vec::new();
+        write_csv(&mut out, Trajectory::new(&Plain, dirs[..2].iter().copied())).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "step,horiz,depth,aim\n0,0,0,0\n1,5,0,0\n2,5,5,0\n"
+        );
+    }
+
+    #[test]
+    fn script() {
+        let script = parse_script(
+            "\
+# dive, then come back up
+set-aim 2
+repeat 3 {
+    forward 1
+    repeat 2 { down 1 }
+
+}
+back 1 # undo the last
+",
+        )
+        .unwrap();
+        assert_eq!(flatten(&script).len(), 11);
+        assert!(Directions::new(&script).eq(flatten(&script)));
+        let loc = Aim.execute(Location::default(), &script).unwrap();
+        assert_eq!(
+            loc,
+            Location {
+                horiz: 2,
+                depth: 2 + 4 + 6 - 8,
+                aim: 8
+            }
+        );
+        assert_eq!(Some(loc), Aim.run(&flatten(&script)));
+        assert_eq!(
+            Plain.execute(Location::default(), &script).unwrap(),
+            Location {
+                horiz: 2,
+                depth: 6,
+                aim: 2
+            }
+        );
+    }
+
+    #[test]
+    fn script_errors() {
+        assert!(parse_script("repeat 2 { forward 1").is_err());
+        assert!(parse_script("forward 1 }").is_err());
+        assert!(parse_script("repeat 2 forward 1").is_err());
+        assert!(parse_script("sideways 2").is_err());
+        assert!(parse_script("forward").is_err());
+        assert!(parse_script("forward\n5").is_err());
+        assert!(parse_script("forward 1 down 2").is_err());
+        assert!(parse_script("repeat 2 { forward 1 down 2 }").is_err());
+        assert!(parse_script("repeat 2 { forward 1 } down 2").is_err());
+        assert!(parse_script("repeat\n2 { forward 1 }").is_err());
+        assert_eq!(
+            parse_script("repeat 2 {\nrepeat 3 {forward 1}}\nup 1")
+                .unwrap()
+                .len(),
+            2
+        );
+        assert_eq!(
+            parse_script("repeat 2 {}").unwrap(),
+            [Command::Repeat(2, vec![])]
+        );
+    }
+
+    // Expands every repeat at once, to check `Directions` against
+    fn flatten(script: &[Command]) -> Vec<Direction> {
+        let mut dirs = Vec::new();
+        for cmd in script {
+            match cmd {
+                Command::Dir(d) => dirs.push(*d),
+                Command::Repeat(n, body) => {
+                    let body = flatten(body);
+                    for _ in 0..*n {
+                        dirs.extend_from_slice(&body);
+                    }
+                }
+            }
+        }
+        dirs
+    }
+
+    // Deterministic pseudo random directions, with plenty of zeros and repeats
+    fn random_dirs(seed: u64, len: usize) -> Vec<Direction> {
+        let mut seed = seed;
+        let mut next = move |n: u64| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) % n
+        };
+        (0..len)
+            .map(|_| {
+                let i = next(4) as i64;
+                match next(5) {
+                    0 => Direction::Forward(i),
+                    1 => Direction::Down(i),
+                    2 => Direction::Up(i),
+                    3 => Direction::Back(i),
+                    _ => Direction::SetAim(i - 2),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        for seed in 0..100 {
+            for d in random_dirs(seed, 20) {
+                assert_eq!(d.to_string().parse::<Direction>().unwrap(), d);
+            }
+            let loc = Aim.run(&random_dirs(seed, 20)).unwrap();
+            assert_eq!(loc.to_string().parse::<Location>().unwrap(), loc);
+        }
+        assert_eq!(Direction::SetAim(-3).to_string(), "set-aim -3");
+        assert_eq!(
+            Location {
+                horiz: 1,
+                depth: -2,
+                aim: 3
+            }
+            .to_string(),
+            "horiz 1 depth -2 aim 3"
+        );
+    }
+
+    #[test]
+    fn normalized() {
+        use Direction::*;
+        assert_eq!(
+            normalize([Forward(1), Up(0), Forward(2), SetAim(1), SetAim(2), Down(0)])
+                .collect::<Vec<_>>(),
+            [Forward(3), SetAim(2)]
+        );
+        for seed in 0..100 {
+            let dirs = random_dirs(seed, 50);
+            let norm: Vec<_> = normalize(dirs.iter().copied()).collect();
+            assert!(norm.len() <= dirs.len());
+            assert_eq!(Plain.run(&norm), Plain.run(&dirs));
+            assert_eq!(Aim.run(&norm), Aim.run(&dirs));
+        }
+    }
+
+    #[test]
+    fn plans_reach_target() {
+        let models: [&dyn Plan; 2] = [&Plain, &Aim];
+        for model in models {
+            for horiz in -12..=12 {
+                for depth in -12..=12 {
+                    for aim in -6..=6 {
+                        let target = Location { horiz, depth, aim };
+                        let plan = model.plan(target).unwrap();
+                        assert_eq!(model.run(&plan).unwrap(), target);
+                        assert!(plan.len() <= 4);
+                        assert!(plan.iter().all(|d| match d {
+                            Direction::SetAim(_) => true,
+                            Direction::Forward(i)
+                            | Direction::Down(i)
+                            | Direction::Up(i)
+                            | Direction::Back(i) => *i > 0,
+                        }));
+                    }
+                }
+            }
+        }
+        let far = Location {
+            horiz: i64::MAX,
+            depth: i64::MIN,
+            aim: 7,
+        };
+        assert_eq!(Aim.plan(far), None);
+    }
+
+    #[test]
+    fn aim_plans_are_minimal() {
+        // Everything reachable in up to three small directions, by length
+        let mut dirs = Vec::new();
+        for i in 0..=4 {
+            dirs.extend([
+                Direction::Forward(i),
+                Direction::Down(i),
+                Direction::Up(i),
+                Direction::Back(i),
+            ]);
+        }
+        dirs.extend((-8..=8).map(Direction::SetAim));
+
+        let mut reachable = vec![vec![Location::default()]];
+        for len in 1..=3 {
+            let mut next: Vec<_> = reachable[len - 1]
+                .iter()
+                .flat_map(|loc| {
+                    dirs.iter()
+                        .map(move |d| Aim.checked_steer(*loc, d).unwrap())
+                })
+                .collect();
+            next.sort_by_key(|l| (l.horiz, l.depth, l.aim));
+            next.dedup();
+            reachable.push(next);
+        }
+
+        for horiz in -2..=2 {
+            for depth in -2..=2 {
+                for aim in -2..=2 {
+                    let target = Location { horiz, depth, aim };
+                    let len = Aim.plan(target).unwrap().len();
+                    for shorter in reachable.iter().take(len) {
+                        assert!(
+                            !shorter.contains(&target),
+                            "{} in fewer than {}",
+                            target,
+                            len
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn envelope() {
+        use Direction::*;
+        let env = Envelope::default();
+        let dirs = parse_test_input();
+        assert_eq!(
+            env.validate(&Aim, dirs.iter().copied()).ok(),
+            Aim.run(&dirs)
+        );
+
+        let flying = [Forward(1), Down(2), Up(3), Forward(1)];
+        assert_eq!(
+            env.validate(&Plain, flying.iter().copied()),
+            Err(Breach {
+                index: 2,
+                dir: Up(3),
+                loc: Location {
+                    horiz: 1,
+                    depth: 2,
+                    aim: 0
+                },
+                violation: Violation::TooShallow(-1)
+            })
+        );
+        let env = Envelope {
+            max_depth: 30,
+            max_aim: 8,
+            ..Default::default()
+        };
+        assert_eq!(
+            env.validate(&Aim, dirs.iter().copied())
+                .unwrap_err()
+                .violation,
+            Violation::TooDeep(40)
+        );
+        assert_eq!(
+            env.validate(&Plain, [Down(5), SetAim(-9)])
+                .unwrap_err()
+                .violation,
+            Violation::AimTooSteep(-9)
+        );
+        let overflow = [SetAim(i64::MAX), Forward(2)];
+        assert_eq!(
+            Envelope::default()
+                .validate(&Aim, overflow)
+                .unwrap_err()
+                .violation,
+            Violation::Overflow
+        );
+    }
+}
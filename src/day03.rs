@@ -1,86 +1,566 @@
-use std::io::{self, BufRead};
+use std::{
+    env, fmt,
+    io::{self, BufRead},
+    str::FromStr,
+    time::Instant,
+};
 
-fn parse_input() -> Vec<i64> {
-    io::stdin()
-        .lock()
-        .lines()
-        .map(|line| i64::from_str_radix(&line.unwrap(), 2).unwrap())
+use color_eyre::eyre::{eyre, Result};
+
+fn parse_input() -> Result<Report> {
+    Report::parse(io::stdin().lock().lines().map(|line| line.unwrap()))
+}
+
+// An unsigned number of any width, stored as little endian 64 bit limbs
+#[derive(Debug, Clone, PartialEq)]
+struct Bits {
+    width: usize,
+    limbs: Vec<u64>,
+}
+
+impl Bits {
+    fn zero(width: usize) -> Self {
+        Self {
+            width,
+            limbs: vec![0; width.div_ceil(64)],
+        }
+    }
+
+    fn set(&mut self, bit: usize) {
+        self.limbs[bit / 64] |= 1 << (bit % 64);
+    }
+
+    fn mul(&self, other: &Bits) -> Bits {
+        let mut out = Bits::zero(self.width + other.width);
+        for (i, &a) in self.limbs.iter().enumerate() {
+            let mut carry = 0u128;
+            for (j, &b) in other.limbs.iter().enumerate() {
+                let t = a as u128 * b as u128 + out.limbs[i + j] as u128 + carry;
+                out.limbs[i + j] = t as u64;
+                carry = t >> 64;
+            }
+            let mut k = i + other.limbs.len();
+            while carry > 0 {
+                let t = out.limbs[k] as u128 + carry;
+                out.limbs[k] = t as u64;
+                carry = t >> 64;
+                k += 1;
+            }
+        }
+        out
+    }
+}
+
+// Base 10, by repeatedly dividing by the largest power of 10 that fits in a limb
+impl fmt::Display for Bits {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const CHUNK: u64 = 10_000_000_000_000_000_000;
+        let mut n = self.limbs.clone();
+        let mut chunks = Vec::new();
+        while n.iter().any(|&l| l != 0) {
+            let mut rem = 0u128;
+            for l in n.iter_mut().rev() {
+                let cur = (rem << 64) | *l as u128;
+                *l = (cur / CHUNK as u128) as u64;
+                rem = cur % CHUNK as u128;
+            }
+            chunks.push(rem as u64);
+        }
+        match chunks.split_last() {
+            None => write!(f, "0"),
+            Some((first, rest)) => {
+                write!(f, "{}", first)?;
+                rest.iter().rev().try_for_each(|c| write!(f, "{:019}", c))
+            }
+        }
+    }
+}
+
+// Base 2, padded to the full width
+impl fmt::Binary for Bits {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (0..self.width)
+            .rev()
+            .try_for_each(|bit| match self.limbs[bit / 64] & 1 << (bit % 64) {
+                0 => write!(f, "0"),
+                _ => write!(f, "1"),
+            })
+    }
+}
+
+// A diagnostic report of equal width words, each stored as little endian 64 bit limbs one after
+// the other so words can be wider than any integer
+#[derive(Debug, Clone, PartialEq)]
+struct Report {
+    width: usize,
+    limbs: usize,
+    data: Vec<u64>,
+}
+
+impl Report {
+    // The width comes from the first line, any line that doesn't match it is an error
+    fn parse<S: AsRef<str>>(lines: impl IntoIterator<Item = S>) -> Result<Self> {
+        let mut report: Option<Report> = None;
+        for (i, line) in lines.into_iter().enumerate() {
+            let line = line.as_ref().trim();
+            let r = report.get_or_insert_with(|| Report {
+                width: line.len(),
+                limbs: line.len().div_ceil(64),
+                data: Vec::new(),
+            });
+            if line.len() != r.width {
+                return Err(eyre!(
+                    "line {} is {} bits wide, expected {}",
+                    i + 1,
+                    line.len(),
+                    r.width
+                ));
+            }
+
+            let start = r.data.len();
+            r.data.resize(start + r.limbs, 0);
+            for (bit, c) in line.bytes().rev().enumerate() {
+                match c {
+                    b'0' => (),
+                    b'1' => r.data[start + bit / 64] |= 1 << (bit % 64),
+                    _ => return Err(eyre!("line {}: invalid bit {:?}", i + 1, c as char)),
+                }
+            }
+        }
+
+        match report {
+            Some(r) if r.width > 0 => Ok(r),
+            _ => Err(eyre!("empty report")),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.data.len() / self.limbs
+    }
+
+    fn bit(&self, word: usize, bit: usize) -> bool {
+        self.data[word * self.limbs + bit / 64] & 1 << (bit % 64) != 0
+    }
+
+    fn word(&self, word: usize) -> Bits {
+        Bits {
+            width: self.width,
+            limbs: self.data[word * self.limbs..(word + 1) * self.limbs].to_vec(),
+        }
+    }
+}
+
+fn count_ones_zeros(input: &Report, words: &[usize], bit: usize) -> (usize, usize) {
+    let ones = words.iter().filter(|&&w| input.bit(w, bit)).count();
+    (ones, words.len() - ones)
+}
+
+// The (ones, zeros) counts of every bit, least significant first, in one pass over the report.
+// Each limb's 64 columns are counted at once by eight accumulators of eight byte-wide counters,
+// where accumulator j counts columns j, j + 8, j + 16 and so on. So every word takes the same
+// eight shifts, masks and adds per limb with no branches, and the bytes are emptied into the
+// totals every 255 words before they can overflow.
+fn column_counts(input: &Report) -> Vec<(usize, usize)> {
+    const LANES: u64 = 0x0101_0101_0101_0101;
+    let mut totals = vec![0; input.limbs * 64];
+    let mut acc = vec![[0u64; 8]; input.limbs];
+    for block in input.data.chunks(255 * input.limbs) {
+        for word in block.chunks_exact(input.limbs) {
+            for (acc, &limb) in acc.iter_mut().zip(word) {
+                for (j, a) in acc.iter_mut().enumerate() {
+                    *a += (limb >> j) & LANES;
+                }
+            }
+        }
+        for (limb, acc) in acc.iter_mut().enumerate() {
+            for (j, a) in acc.iter_mut().enumerate() {
+                for lane in 0..8 {
+                    totals[limb * 64 + lane * 8 + j] += (*a >> (lane * 8) & 0xff) as usize;
+                }
+                *a = 0;
+            }
+        }
+    }
+
+    totals[..input.width]
+        .iter()
+        .map(|&ones| (ones, input.len() - ones))
         .collect()
 }
 
-fn count_ones_zeros(input: &[i64], bit: usize) -> (usize, usize) {
-    let ones = input.iter().filter(|n| *n & 1 << bit == 1 << bit).count();
-    (ones, input.len() - ones)
+// Picks a bit from its (ones, zeros) counts, or `None` if the counts are tied
+type Criterion = fn((usize, usize)) -> Option<bool>;
+
+fn most_common((ones, zeros): (usize, usize)) -> Option<bool> {
+    (ones != zeros).then_some(ones > zeros)
+}
+
+fn least_common((ones, zeros): (usize, usize)) -> Option<bool> {
+    (ones != zeros).then_some(ones < zeros)
+}
+
+// What to do when a criterion can't pick a bit
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Tie {
+    One,
+    Zero,
+    Error,
+    // Only makes sense for the ratings, where it keeps every word
+    Both,
+}
+
+impl FromStr for Tie {
+    type Err = color_eyre::eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "one" => Ok(Tie::One),
+            "zero" => Ok(Tie::Zero),
+            "error" => Ok(Tie::Error),
+            "both" => Ok(Tie::Both),
+            _ => Err(eyre!("unknown tie breaker: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Rule {
+    criterion: Criterion,
+    tie: Tie,
+}
+
+impl Rule {
+    // Which bit to keep, `None` meaning either
+    fn keep(&self, counts: (usize, usize), bit: usize) -> Result<Option<bool>> {
+        match ((self.criterion)(counts), self.tie) {
+            (Some(one), _) => Ok(Some(one)),
+            (None, Tie::One) => Ok(Some(true)),
+            (None, Tie::Zero) => Ok(Some(false)),
+            (None, Tie::Error) => Err(eyre!("tie at bit {}", bit)),
+            (None, Tie::Both) => Ok(None),
+        }
+    }
+}
+
+// `most` gives gamma and the oxygen rating, `least` gives epsilon and the CO2 rating. The default
+// is the puzzle's, ties go to one for the most common and zero for the least common
+#[derive(Debug, Clone, Copy)]
+struct Rules {
+    most: Rule,
+    least: Rule,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            most: Rule {
+                criterion: most_common,
+                tie: Tie::One,
+            },
+            least: Rule {
+                criterion: least_common,
+                tie: Tie::Zero,
+            },
+        }
+    }
 }
 
-fn ones_most_common(input: &[i64], bit: usize) -> bool {
-    let (ones, zeros) = count_ones_zeros(input, bit);
-    ones >= zeros
+fn gamma_epsilon(input: &Report, rules: &Rules) -> Result<(Bits, Bits)> {
+    let counts = column_counts(input);
+    let (mut gamma, mut epsi) = (Bits::zero(input.width), Bits::zero(input.width));
+    for bit in (0..input.width).rev() {
+        let counts = counts[bit];
+        for (rule, rate) in [(&rules.most, &mut gamma), (&rules.least, &mut epsi)] {
+            match rule.keep(counts, bit)? {
+                Some(true) => rate.set(bit),
+                Some(false) => (),
+                None => return Err(eyre!("tie at bit {} can't be both in a rate", bit)),
+            }
+        }
+    }
+    Ok((gamma, epsi))
 }
 
-fn ones_least_common(input: &[i64], bit: usize) -> bool {
-    let (ones, zeros) = count_ones_zeros(input, bit);
-    ones < zeros
+fn ratings(input: &Report, rules: &Rules) -> Result<(Bits, Bits)> {
+    let sorted = Sorted::new(input);
+    let o2 = sorted.rating(&rules.most)?;
+    let co2 = sorted.rating(&rules.least)?;
+    Ok((input.word(o2), input.word(co2)))
 }
 
-fn run_part_one(input: &[i64], bits: usize) -> i64 {
-    let (gamma, epsi) = (0..bits).rev().fold((0, 0), |(mut g, mut e), bit| {
-        if ones_most_common(input, bit) {
-            g |= 1 << bit;
+// The report's word indexes sorted most significant bit first, so the words sharing any prefix
+// are one range of it, with the ones that have a zero at the next bit before the ones that have
+// a one. Sorting costs less than partitioning for both ratings does, and after that each rating
+// only narrows a range with a binary search per bit.
+struct Sorted<'a> {
+    input: &'a Report,
+    words: Vec<usize>,
+}
+
+impl<'a> Sorted<'a> {
+    fn new(input: &'a Report) -> Self {
+        let mut words: Vec<_> = (0..input.len()).collect();
+        let limbs = |w: &usize| {
+            input.data[w * input.limbs..(w + 1) * input.limbs]
+                .iter()
+                .rev()
+        };
+        // When a word and its index fit in a u64 together they sort as plain integers, which is
+        // several times quicker than comparing through the indexes
+        let index_bits = usize::BITS - input.len().leading_zeros();
+        if input.width + index_bits as usize <= 64 {
+            let mut packed: Vec<_> = words
+                .iter()
+                .map(|&w| input.data[w] << index_bits | w as u64)
+                .collect();
+            packed.sort_unstable();
+            for (w, p) in words.iter_mut().zip(packed) {
+                *w = (p & ((1 << index_bits) - 1)) as usize;
+            }
         } else {
-            e |= 1 << bit;
+            words.sort_unstable_by(|a, b| limbs(a).cmp(limbs(b)));
+        }
+        Sorted { input, words }
+    }
+
+    // Same as `rating` but narrows ranges of the sorted words instead. That's one range unless a
+    // tie keeps both, in which case every kept range splits in two at the tied bit
+    fn rating(&self, rule: &Rule) -> Result<usize> {
+        let mut ranges = vec![(0, self.words.len())];
+        let mut next = Vec::new();
+        for bit in (0..self.input.width).rev() {
+            if let [(lo, hi)] = ranges[..] {
+                if hi - lo == 1 {
+                    return Ok(self.words[lo]);
+                }
+            }
+            let splits: Vec<_> = ranges
+                .iter()
+                .map(|&(lo, hi)| {
+                    let zeros = self.words[lo..hi].partition_point(|&w| !self.input.bit(w, bit));
+                    lo + zeros
+                })
+                .collect();
+            let (ones, zeros) = ranges
+                .iter()
+                .zip(&splits)
+                .fold((0, 0), |(o, z), (&(lo, hi), &split)| {
+                    (o + hi - split, z + split - lo)
+                });
+            let keep = rule.keep((ones, zeros), bit)?;
+            for (&(lo, hi), &split) in ranges.iter().zip(&splits) {
+                for (one, range) in [(false, (lo, split)), (true, (split, hi))] {
+                    if range.0 < range.1 && keep.is_none_or(|k| k == one) {
+                        next.push(range);
+                    }
+                }
+            }
+            if next.is_empty() {
+                return Err(eyre!("no words left after bit {}", bit));
+            }
+            ranges.clear();
+            std::mem::swap(&mut ranges, &mut next);
         }
-        (g, e)
-    });
 
-    gamma * epsi
+        match ranges.iter().map(|(lo, hi)| hi - lo).sum() {
+            1 => Ok(self.words[ranges[0].0]),
+            n => Err(eyre!("no unique rating, {} words left", n)),
+        }
+    }
 }
 
-fn run_part_two(input: &[i64], bits: usize) -> i64 {
-    let o2 = find_o2(input.to_vec(), bits - 1);
-    let co2 = find_co2(input.to_vec(), bits - 1);
+// Narrows down to the one word that matches the rule at every bit, by partitioning the
+// remaining words in place so the ones that are kept come first
+fn rating(input: &Report, rule: &Rule) -> Result<usize> {
+    let mut words: Vec<_> = (0..input.len()).collect();
+    let mut left = words.len();
+    for bit in (0..input.width).rev() {
+        if left == 1 {
+            break;
+        }
+        let ones = match rule.keep(count_ones_zeros(input, &words[..left], bit), bit)? {
+            Some(ones) => ones,
+            None => continue,
+        };
+        let mut kept = 0;
+        for i in 0..left {
+            if input.bit(words[i], bit) == ones {
+                words.swap(i, kept);
+                kept += 1;
+            }
+        }
+        if kept == 0 {
+            return Err(eyre!("no words left after bit {}", bit));
+        }
+        left = kept;
+    }
+
+    match left {
+        1 => Ok(words[0]),
+        n => Err(eyre!("no unique rating, {} words left", n)),
+    }
+}
 
-    o2[0] * co2[0]
+fn run_part_one(input: &Report, rules: &Rules) -> Result<Bits> {
+    let (gamma, epsi) = gamma_epsilon(input, rules)?;
+    Ok(gamma.mul(&epsi))
 }
 
-fn find_o2(input: Vec<i64>, bit: usize) -> Vec<i64> {
-    let is_ones = ones_most_common(&input, bit);
-    let smaller = subset(input, is_ones, bit);
-    if smaller.len() == 1 {
-        return smaller;
+fn run_part_two(input: &Report, rules: &Rules) -> Result<Bits> {
+    let (o2, co2) = ratings(input, rules)?;
+    Ok(o2.mul(&co2))
+}
+
+// Everything worked out from a report, for printing in one go
+#[derive(Debug, Clone, PartialEq)]
+struct DiagnosticReport {
+    gamma: Bits,
+    epsilon: Bits,
+    power: Bits,
+    o2: Bits,
+    co2: Bits,
+    life_support: Bits,
+    // (ones, zeros) for each bit, most significant first
+    counts: Vec<(usize, usize)>,
+}
+
+impl DiagnosticReport {
+    fn new(input: &Report, rules: &Rules) -> Result<Self> {
+        let (gamma, epsilon) = gamma_epsilon(input, rules)?;
+        let (o2, co2) = ratings(input, rules)?;
+        Ok(Self {
+            power: gamma.mul(&epsilon),
+            life_support: o2.mul(&co2),
+            gamma,
+            epsilon,
+            o2,
+            co2,
+            counts: column_counts(input).into_iter().rev().collect(),
+        })
     }
-    find_o2(smaller, bit - 1)
 }
 
-fn find_co2(input: Vec<i64>, bit: usize) -> Vec<i64> {
-    let ones = ones_least_common(&input, bit);
-    let smaller = subset(input, ones, bit);
-    if smaller.len() == 1 {
-        return smaller;
+impl fmt::Display for DiagnosticReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rows = [
+            ("gamma", &self.gamma),
+            ("epsilon", &self.epsilon),
+            ("power", &self.power),
+            ("oxygen", &self.o2),
+            ("co2", &self.co2),
+            ("life support", &self.life_support),
+        ];
+        for (name, n) in rows {
+            writeln!(f, "{:<13}{:b} ({})", name, n, n)?;
+        }
+        writeln!(f, "{:>5} {:>8} {:>8}", "bit", "ones", "zeros")?;
+        let width = self.counts.len();
+        for (i, (ones, zeros)) in self.counts.iter().enumerate() {
+            writeln!(f, "{:>5} {:>8} {:>8}", width - 1 - i, ones, zeros)?;
+        }
+        Ok(())
     }
-    find_co2(smaller, bit - 1)
 }
 
-fn subset(input: Vec<i64>, ones: bool, bit: usize) -> Vec<i64> {
-    if ones {
-        input
-            .iter()
-            .filter(|n| *n & 1 << bit == 1 << bit)
-            .copied()
-            .collect()
-    } else {
-        input
-            .iter()
-            .filter(|n| (*n ^ 1 << bit) & 1 << bit == 1 << bit)
-            .copied()
-            .collect()
+// Every word of a width once, in a random order. Random words usually run out of words with the
+// least common bit before the CO2 rating is found, but here every rating walks the full width
+fn every_word(width: usize, mut seed: u64) -> Report {
+    let mut data: Vec<u64> = (0..1 << width).collect();
+    for i in (1..data.len()).rev() {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        data.swap(i, (seed >> 33) as usize % (i + 1));
+    }
+    Report {
+        width,
+        limbs: 1,
+        data,
     }
 }
 
-fn main() {
-    let input = parse_input();
-    println!("part 1: {}", run_part_one(&input, 12));
-    println!("part 2: {}", run_part_two(&input, 12));
+// Times counting every column one at a time against counting them all at once with byte lanes,
+// and the partitioning and sorted range ratings against each other
+fn bench(width: usize) -> Result<()> {
+    if !(1..=32).contains(&width) {
+        return Err(eyre!("--bench takes a width from 1 to 32"));
+    }
+    let input = every_word(width, 3);
+    let time = |name: &str, f: &dyn Fn(&Rule) -> Result<usize>| -> Result<[Bits; 2]> {
+        let start = Instant::now();
+        let rules = Rules::default();
+        let o2 = f(&rules.most)?;
+        let co2 = f(&rules.least)?;
+        println!("{:<16}{:?}", name, start.elapsed());
+        Ok([input.word(o2), input.word(co2)])
+    };
+
+    println!("{} words of {} bits", input.len(), input.width);
+    let partition = time("partition", &|keep| rating(&input, keep))?;
+    let start = Instant::now();
+    let sorted = Sorted::new(&input);
+    println!("{:<16}{:?}", "sort", start.elapsed());
+    let walked = time("ranges", &|keep| sorted.rating(keep))?;
+
+    if partition != walked {
+        return Err(eyre!("ratings differ: {:?} vs {:?}", partition, walked));
+    }
+    println!("oxygen: {:b}\nco2: {:b}", walked[0], walked[1]);
+
+    let all: Vec<_> = (0..input.len()).collect();
+    let start = Instant::now();
+    let columns: Vec<_> = (0..input.width)
+        .map(|bit| count_ones_zeros(&input, &all, bit))
+        .collect();
+    println!("{:<16}{:?}", "columns", start.elapsed());
+    let start = Instant::now();
+    let lanes = column_counts(&input);
+    println!("{:<16}{:?}", "byte lanes", start.elapsed());
+    if columns != lanes {
+        return Err(eyre!("column counts differ"));
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let mut report = false;
+    let mut rules = Rules::default();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &*arg {
+            // Print every value in binary and decimal along with the bit counts
+            "--report" => report = true,
+            // Every word of a width instead of the input, e.g. --bench 20 for a million lines
+            "--bench" => {
+                let width = args
+                    .next()
+                    .ok_or_else(|| eyre!("--bench requires a width"))?
+                    .parse()?;
+                return bench(width);
+            }
+            // How to break ties for gamma and oxygen, or epsilon and CO2
+            "--most-tie" | "--least-tie" => {
+                let tie = args
+                    .next()
+                    .ok_or_else(|| eyre!("{} requires one, zero, error or both", arg))?
+                    .parse()?;
+                match &*arg {
+                    "--most-tie" => rules.most.tie = tie,
+                    _ => rules.least.tie = tie,
+                }
+            }
+            _ => return Err(eyre!("unknown argument: {}", arg)),
+        }
+    }
+
+    let input = parse_input()?;
+    if report {
+        print!("{}", DiagnosticReport::new(&input, &rules)?);
+        return Ok(());
+    }
+    println!("part 1: {}", run_part_one(&input, &rules)?);
+    println!("part 2: {}", run_part_two(&input, &rules)?);
+    Ok(())
 }
 
 #[cfg(test)]
@@ -101,21 +581,223 @@ mod tests {
 00010
 01010";
 
-    fn parse_test_input(input: &str) -> Vec<i64> {
-        input
-            .lines()
-            .map(|line| i64::from_str_radix(&line, 2).unwrap())
-            .collect()
+    fn parse_test_input(input: &str) -> Report {
+        Report::parse(input.lines()).unwrap()
+    }
+
+    // Words from a simple LCG
+    fn random_report(len: usize, width: usize, mut seed: u64) -> Report {
+        let limbs = width.div_ceil(64);
+        let mut data = Vec::with_capacity(len * limbs);
+        for _ in 0..len {
+            for limb in 0..limbs {
+                let mut n = 0;
+                for _ in 0..2 {
+                    seed = seed
+                        .wrapping_mul(6364136223846793005)
+                        .wrapping_add(1442695040888963407);
+                    n = n << 32 | seed >> 32;
+                }
+                // Only the bits that are part of the word
+                match width - limb * 64 {
+                    w if w < 64 => data.push(n & ((1 << w) - 1)),
+                    _ => data.push(n),
+                }
+            }
+        }
+        Report { width, limbs, data }
     }
 
     #[test]
     fn test_part1() {
-        assert_eq!(run_part_one(&parse_test_input(TEST_INPUT), 5), 198);
+        assert_eq!(
+            run_part_one(&parse_test_input(TEST_INPUT), &Rules::default())
+                .unwrap()
+                .to_string(),
+            "198"
+        );
     }
 
     #[test]
     fn test_part2() {
         let inp = parse_test_input(TEST_INPUT);
-        assert_eq!(run_part_two(&inp, 5), 230);
+        assert_eq!(
+            run_part_two(&inp, &Rules::default()).unwrap().to_string(),
+            "230"
+        );
+    }
+
+    #[test]
+    fn wide() {
+        // The example shifted up past 64 bits, which leaves the low bits all zero
+        let wide: String = TEST_INPUT
+            .lines()
+            .map(|l| format!("{}{}\n", l, "0".repeat(65)))
+            .collect();
+        let inp = parse_test_input(&wide);
+        assert_eq!((inp.width, inp.len()), (70, 12));
+        // gamma is 22 << 65 and epsilon is 9 << 65 with the low 65 bits set
+        assert_eq!(
+            run_part_one(&inp, &Rules::default()).unwrap().to_string(),
+            "299448482890425847846957997800712805810176"
+        );
+        assert_eq!(
+            run_part_two(&inp, &Rules::default()).unwrap().to_string(),
+            (0..130)
+                .fold(
+                    Bits {
+                        width: 8,
+                        limbs: vec![230]
+                    },
+                    |acc, _| {
+                        acc.mul(&Bits {
+                            width: 2,
+                            limbs: vec![2],
+                        })
+                    }
+                )
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn report() {
+        let report =
+            DiagnosticReport::new(&parse_test_input(TEST_INPUT), &Rules::default()).unwrap();
+        assert_eq!(report.counts[0], (7, 5));
+        assert_eq!(report.counts[4], (5, 7));
+        assert_eq!(
+            report.to_string(),
+            "\
+gamma        10110 (22)
+epsilon      01001 (9)
+power        0011000110 (198)
+oxygen       10111 (23)
+co2          01010 (10)
+life support 0011100110 (230)
+  bit     ones    zeros
+    4        7        5
+    3        5        7
+    2        8        4
+    1        7        5
+    0        5        7
+"
+        );
+    }
+
+    #[test]
+    fn no_unique_rating() {
+        let dupes = parse_test_input("101\n101\n011");
+        assert!(run_part_two(&dupes, &Rules::default()).is_err());
+        // Every word has a zero in the top bit, so none has the least common one
+        let same = parse_test_input("01\n00");
+        assert!(run_part_two(&same, &Rules::default()).is_err());
+        let single = parse_test_input("110");
+        assert_eq!(
+            run_part_two(&single, &Rules::default())
+                .unwrap()
+                .to_string(),
+            "36"
+        );
+    }
+
+    fn all_rules() -> Vec<Rule> {
+        let ties = [Tie::One, Tie::Zero, Tie::Error, Tie::Both];
+        let criteria: [Criterion; 2] = [most_common, least_common];
+        criteria
+            .into_iter()
+            .flat_map(|criterion| ties.map(|tie| Rule { criterion, tie }))
+            .collect()
+    }
+
+    #[test]
+    fn ties() {
+        let inp = parse_test_input(TEST_INPUT);
+        let mut rules = Rules::default();
+        // Oxygen is down to 10110 and 10111 at the last bit
+        rules.most.tie = Tie::Zero;
+        assert_eq!(run_part_two(&inp, &rules).unwrap().to_string(), "220");
+        rules.most.tie = Tie::Both;
+        assert!(run_part_two(&inp, &rules).is_err());
+        rules.most.tie = Tie::Error;
+        assert!(run_part_two(&inp, &rules).is_err());
+        // No bit is tied over the whole report
+        assert_eq!(run_part_one(&inp, &rules).unwrap().to_string(), "198");
+
+        let tied = parse_test_input("10\n01\n11\n00");
+        assert!(run_part_one(&tied, &rules).is_err());
+        rules.most.tie = Tie::Both;
+        assert!(run_part_one(&tied, &rules).is_err());
+
+        // Keeping both at the top bit, then 10 is the only word with the least common low bit
+        let words = parse_test_input("11\n10\n01\n01");
+        let both = Rule {
+            criterion: least_common,
+            tie: Tie::Both,
+        };
+        assert_eq!(rating(&words, &both).unwrap(), 1);
+        assert_eq!(Sorted::new(&words).rating(&both).unwrap(), 1);
+    }
+
+    #[test]
+    fn custom_criterion() {
+        let inp = parse_test_input(TEST_INPUT);
+        let mut rules = Rules::default();
+        rules.most.criterion = |(ones, _)| Some(ones % 2 == 1);
+        let (gamma, _) = gamma_epsilon(&inp, &rules).unwrap();
+        assert_eq!(format!("{:b}", gamma), "11011");
+        assert_eq!(run_part_one(&inp, &rules).unwrap().to_string(), "243");
+    }
+
+    #[test]
+    fn column_counts_match() {
+        for (len, width) in [(1, 1), (12, 5), (1000, 64), (5000, 70), (300, 130)] {
+            let inp = random_report(len, width, 7);
+            let all: Vec<_> = (0..len).collect();
+            let naive: Vec<_> = (0..width)
+                .map(|bit| count_ones_zeros(&inp, &all, bit))
+                .collect();
+            assert_eq!(column_counts(&inp), naive, "{} {}", len, width);
+        }
+        // Full byte counters, and more words than fit in them
+        for len in [255, 256, 1023] {
+            let ones = parse_test_input(&"111\n".repeat(len));
+            assert_eq!(column_counts(&ones), vec![(len, 0); 3]);
+        }
+    }
+
+    #[test]
+    fn sorted_matches_partition() {
+        // Narrow words so there are plenty of duplicates and dead ends too
+        for (len, width) in [(1, 3), (5, 3), (20, 4), (100, 8), (1000, 16), (500, 70)] {
+            for seed in 0..20 {
+                let inp = random_report(len, width, seed);
+                let sorted = Sorted::new(&inp);
+                for keep in all_rules() {
+                    let (a, b) = (rating(&inp, &keep), sorted.rating(&keep));
+                    assert_eq!(a.is_ok(), b.is_ok(), "{} {} {}", len, width, seed);
+                    if let (Ok(a), Ok(b)) = (a, b) {
+                        assert_eq!(inp.word(a), inp.word(b));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(Report::parse(["0101", "011"]).is_err());
+        assert!(Report::parse(["0121"]).is_err());
+        assert!(Report::parse(Vec::<&str>::new()).is_err());
+    }
+
+    #[test]
+    fn decimal() {
+        let big = Bits {
+            width: 128,
+            limbs: vec![u64::MAX, u64::MAX],
+        };
+        assert_eq!(big.to_string(), u128::MAX.to_string());
+        assert_eq!(Bits::zero(70).to_string(), "0");
     }
 }
@@ -3,12 +3,13 @@ use std::{
     str::FromStr,
 };
 
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{eyre, Result};
 
 fn main() -> Result<()> {
     let input = parse_input();
     println!("part 1: {}", part_one(&input));
-    println!("part 2: {}", part_two(&input));
+    let two = part_two(&input).ok_or_else(|| eyre!("part 2 overflows usize"))?;
+    println!("part 2: {}", two);
 
     Ok(())
 }
@@ -56,20 +57,11 @@ fn part_one(input: &[RawInput]) -> usize {
         .count()
 }
 
-fn part_two(input: &[RawInput]) -> usize {
-    input
-        .iter()
-        .map(|ri| {
-            let n = Numeral::new(&*ri.signals);
-            ri.outputs
-                .iter()
-                .map(|o| n.decode(o))
-                .map(|n| format!("{}", n))
-                .collect::<String>()
-                .parse::<usize>()
-                .unwrap()
-        })
-        .sum()
+// None if an output value or their sum overflows
+fn part_two(input: &[RawInput]) -> Option<usize> {
+    input.iter().try_fold(0usize, |sum, ri| {
+        sum.checked_add(Numeral::new(&ri.signals).decode_value(&ri.outputs)?)
+    })
 }
 
 // Find the char from 'a' that is missing from 'b'
@@ -158,7 +150,19 @@ impl Numeral {
         numeral
     }
 
-    fn decode(&self, coded: &[char]) -> usize {
+    // Decodes each output pattern into its digit, most significant first
+    fn decode_digits<'a>(&'a self, outputs: &'a [Vec<char>]) -> impl Iterator<Item = u8> + 'a {
+        outputs.iter().map(move |o| self.decode(o))
+    }
+
+    // Decodes the outputs as a single base 10 number, or None if it doesn't fit in a usize
+    fn decode_value(&self, outputs: &[Vec<char>]) -> Option<usize> {
+        self.decode_digits(outputs).try_fold(0usize, |acc, d| {
+            acc.checked_mul(10)?.checked_add(d as usize)
+        })
+    }
+
+    fn decode(&self, coded: &[char]) -> u8 {
         match (
             coded.contains(&self.a.unwrap()),
             coded.contains(&self.b.unwrap()),
@@ -219,11 +223,36 @@ gcafb gcf dcaebfg ecagb gf abcdeg gaef cafbge fdbac fegbdc | fgae cfgab fg bagce
     #[test]
     fn p_two_example() {
         let inputs = parse_test_input(EXAMPLE);
-        assert_eq!(part_two(&inputs), 5353);
+        assert_eq!(part_two(&inputs), Some(5353));
+    }
+
+    #[test]
+    fn decode_digits() {
+        let ri = EXAMPLE.parse::<RawInput>().unwrap();
+        let n = Numeral::new(&ri.signals);
+        assert_eq!(
+            n.decode_digits(&ri.outputs).collect::<Vec<_>>(),
+            [5, 3, 5, 3]
+        );
+        assert_eq!(n.decode_value(&ri.outputs), Some(5353));
+    }
+
+    #[test]
+    fn decode_value_overflow() {
+        let ri = EXAMPLE.parse::<RawInput>().unwrap();
+        let n = Numeral::new(&ri.signals);
+        // "cdfeb" decodes to 5, and a usize holds one digit fewer than usize::MAX has
+        let fits = usize::MAX.ilog10() as usize;
+        let outputs = vec!["cdfeb".chars().collect::<Vec<_>>(); fits + 1];
+        assert_eq!(n.decode_value(&outputs), None);
+        assert_eq!(
+            n.decode_value(&outputs[..fits]),
+            Some("5".repeat(fits).parse().unwrap())
+        );
     }
     #[test]
     fn p_two() {
         let inputs = parse_test_input(TEST_INPUT);
-        assert_eq!(part_two(&inputs), 61229);
+        assert_eq!(part_two(&inputs), Some(61229));
     }
 }
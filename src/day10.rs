@@ -1,13 +1,33 @@
 use std::{
     collections::VecDeque,
-    io::{self, BufRead},
+    env, fmt, fs,
+    io::{self, BufRead, Read, Write},
+    mem, process,
+    str::FromStr,
 };
 
+use color_eyre::eyre::{eyre, Result};
+
+// Generic over the kind of brace so user defined delimiter sets can be checked too, in which
+// case the kind is the index of the pair in the `DelimSet`
 #[derive(Clone, Debug, PartialEq)]
-enum ErrorKind {
-    // Expected, Found
-    Illegal(BraceKind, BraceKind),
-    Incomplete(Vec<BraceKind>),
+enum Diagnostic<K = BraceKind> {
+    Valid,
+    // Opener it was compared to, Found
+    Illegal(BraceAt<K>, BraceAt<K>),
+    // A closer found with nothing left open
+    Unopened(BraceAt<K>),
+    // Closers required to complete the line, innermost first
+    Incomplete(Vec<K>),
+    // A character that isn't part of any delimiter, when those are rejected
+    Unknown(usize, char),
+}
+
+// A brace along with its byte offset into the line
+#[derive(Debug, PartialEq, Copy, Clone)]
+struct BraceAt<K = BraceKind> {
+    offset: usize,
+    kind: K,
 }
 
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -19,31 +39,114 @@ enum BraceKind {
 }
 
 #[derive(Debug, PartialEq, Copy, Clone)]
-enum Brace {
-    Open(BraceKind),
-    Close(BraceKind),
+enum Brace<K = BraceKind> {
+    Open(K),
+    Close(K),
 }
 
-impl From<char> for Brace {
-    fn from(c: char) -> Self {
+impl BraceKind {
+    const ALL: [BraceKind; 4] = [
+        BraceKind::Paren,
+        BraceKind::Sqr,
+        BraceKind::Curl,
+        BraceKind::Angl,
+    ];
+
+    fn open_char(self) -> char {
+        match self {
+            BraceKind::Paren => '(',
+            BraceKind::Sqr => '[',
+            BraceKind::Angl => '<',
+            BraceKind::Curl => '{',
+        }
+    }
+
+    fn close_char(self) -> char {
+        match self {
+            BraceKind::Paren => ')',
+            BraceKind::Sqr => ']',
+            BraceKind::Angl => '>',
+            BraceKind::Curl => '}',
+        }
+    }
+}
+
+impl TryFrom<char> for Brace {
+    type Error = char;
+    fn try_from(c: char) -> Result<Self, Self::Error> {
         match c {
-            '(' => Brace::Open(BraceKind::Paren),
-            ')' => Brace::Close(BraceKind::Paren),
-            '[' => Brace::Open(BraceKind::Sqr),
-            ']' => Brace::Close(BraceKind::Sqr),
-            '{' => Brace::Open(BraceKind::Curl),
-            '}' => Brace::Close(BraceKind::Curl),
-            '<' => Brace::Open(BraceKind::Angl),
-            '>' => Brace::Close(BraceKind::Angl),
-            _ => unreachable!(),
+            '(' => Ok(Brace::Open(BraceKind::Paren)),
+            ')' => Ok(Brace::Close(BraceKind::Paren)),
+            '[' => Ok(Brace::Open(BraceKind::Sqr)),
+            ']' => Ok(Brace::Close(BraceKind::Sqr)),
+            '{' => Ok(Brace::Open(BraceKind::Curl)),
+            '}' => Ok(Brace::Close(BraceKind::Curl)),
+            '<' => Ok(Brace::Open(BraceKind::Angl)),
+            '>' => Ok(Brace::Close(BraceKind::Angl)),
+            _ => Err(c),
         }
     }
 }
 
-fn main() {
-    let input = parse_input();
-    println!("part one: {}", part_one(&*input));
-    println!("part two: {}", part_two(&*input));
+// Tracks the currently open braces as braces are fed in one at a time
+#[derive(Debug)]
+struct Checker<K = BraceKind> {
+    stack: VecDeque<BraceAt<K>>,
+}
+
+impl<K> Default for Checker<K> {
+    fn default() -> Self {
+        Self {
+            stack: VecDeque::new(),
+        }
+    }
+}
+
+impl<K: Copy + PartialEq> Checker<K> {
+    // Returns the diagnostic if the brace at `offset` is illegal
+    fn feed(&mut self, b: Brace<K>, offset: usize) -> Option<Diagnostic<K>> {
+        match b {
+            Brace::Open(kind) => {
+                self.stack.push_back(BraceAt { offset, kind });
+                None
+            }
+            Brace::Close(kind) => {
+                let bc = BraceAt { offset, kind };
+                match self.stack.pop_back() {
+                    Some(bo) if bo.kind != bc.kind => Some(Diagnostic::Illegal(bo, bc)),
+                    Some(_) => None,
+                    None => Some(Diagnostic::Unopened(bc)),
+                }
+            }
+        }
+    }
+
+    fn finish(self) -> Diagnostic<K> {
+        if self.stack.is_empty() {
+            return Diagnostic::Valid;
+        }
+        Diagnostic::Incomplete(self.stack.into_iter().rev().map(|bo| bo.kind).collect())
+    }
+}
+
+fn main() -> Result<()> {
+    let mut args = env::args().skip(1);
+    if let Some(mode) = args.next() {
+        return match &*mode {
+            "bracket-lint" => bracket_lint(args),
+            "check" => check(args),
+            "repair" => repair_lines(),
+            "stream" => stream(args),
+            "score" => score(args),
+            "report" => report(args),
+            "structure" => structure(args),
+            _ => Err(eyre!("unknown mode: {}", mode)),
+        };
+    }
+
+    print_parts(&parse_input(), &Scoring::default());
+
+    Ok(())
 }
 
 fn parse_input() -> Vec<Vec<char>> {
@@ -54,82 +157,1298 @@ fn parse_input() -> Vec<Vec<char>> {
         .collect()
 }
 
-fn part_one(input: &[Vec<char>]) -> usize {
-    use BraceKind::*;
-    let mut e_paren = 0;
-    let mut e_ang = 0;
-    let mut e_sqr = 0;
-    let mut e_curl = 0;
-    for v in input {
-        match find_errs(v) {
-            Ok(_) => (),
-            Err(ErrorKind::Illegal(_bo, bc)) => match bc {
-                Paren => e_paren += 1,
-                Angl => e_ang += 1,
-                Sqr => e_sqr += 1,
-                Curl => e_curl += 1,
+fn part_one(input: &[Vec<char>], scoring: &Scoring) -> Option<usize> {
+    scoring.corrupted_agg.apply(
+        input
+            .iter()
+            .filter_map(|v| scoring.corrupted_score(&find_errs(v)))
+            .collect(),
+    )
+}
+
+fn find_errs(v: &[char]) -> Diagnostic {
+    let mut checker = Checker::default();
+    let mut offset = 0;
+    for c in v {
+        let b = match Brace::try_from(*c) {
+            Ok(b) => b,
+            Err(c) => return Diagnostic::Unknown(offset, c),
+        };
+        if let Some(d) = checker.feed(b, offset) {
+            return d;
+        }
+        offset += c.len_utf8();
+    }
+
+    checker.finish()
+}
+
+fn part_two(input: &[Vec<char>], scoring: &Scoring) -> Option<usize> {
+    scoring.completion_agg.apply(
+        input
+            .iter()
+            .filter_map(|v| match find_errs(v) {
+                Diagnostic::Incomplete(v) => Some(scoring.completion_score(&v)),
+                _ => None,
+            })
+            .collect(),
+    )
+}
+
+// Points for each kind of brace
+#[derive(Debug, PartialEq, Copy, Clone)]
+struct Weights {
+    paren: usize,
+    sqr: usize,
+    curl: usize,
+    angl: usize,
+}
+
+impl Weights {
+    fn get(&self, kind: BraceKind) -> usize {
+        match kind {
+            BraceKind::Paren => self.paren,
+            BraceKind::Sqr => self.sqr,
+            BraceKind::Curl => self.curl,
+            BraceKind::Angl => self.angl,
+        }
+    }
+}
+
+// Weights are listed in `( [ { <` order
+impl FromStr for Weights {
+    type Err = color_eyre::eyre::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let w = s
+            .split(',')
+            .map(|n| n.trim().parse())
+            .collect::<Result<Vec<usize>, _>>()?;
+        match w[..] {
+            [paren, sqr, curl, angl] => Ok(Weights {
+                paren,
+                sqr,
+                curl,
+                angl,
+            }),
+            _ => Err(eyre!("expected four weights: {}", s)),
+        }
+    }
+}
+
+// How each line's score is combined into a single answer
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum Aggregate {
+    // The middle score, or the lower of the two middle scores for an even count
+    Median,
+    Sum,
+    Max,
+}
+
+impl Aggregate {
+    // None when there's nothing to take the median or max of
+    fn apply(self, mut scores: Vec<usize>) -> Option<usize> {
+        match self {
+            // Saturates like the completion scores it adds up
+            Aggregate::Sum => Some(scores.iter().fold(0, |acc, &s| acc.saturating_add(s))),
+            Aggregate::Max => scores.into_iter().max(),
+            Aggregate::Median if scores.is_empty() => None,
+            Aggregate::Median => {
+                let mid = (scores.len() - 1) / 2;
+                Some(*scores.select_nth_unstable(mid).1)
+            }
+        }
+    }
+}
+
+impl FromStr for Aggregate {
+    type Err = color_eyre::eyre::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "median" => Ok(Aggregate::Median),
+            "sum" => Ok(Aggregate::Sum),
+            "max" => Ok(Aggregate::Max),
+            _ => Err(eyre!("invalid aggregate: {}", s)),
+        }
+    }
+}
+
+// How corrupted and incomplete lines are scored, the default is the puzzle's
+#[derive(Debug, PartialEq, Clone)]
+struct Scoring {
+    // Points for a corrupted line by its illegal closer
+    corrupted: Weights,
+    corrupted_agg: Aggregate,
+    // Points for each closer that completes an incomplete line
+    completion: Weights,
+    // The completion score is multiplied by this before each closer's points are added
+    base: usize,
+    completion_agg: Aggregate,
+}
+
+impl Default for Scoring {
+    fn default() -> Self {
+        Self {
+            corrupted: Weights {
+                paren: 3,
+                sqr: 57,
+                curl: 1197,
+                angl: 25137,
             },
-            _ => (),
+            corrupted_agg: Aggregate::Sum,
+            completion: Weights {
+                paren: 1,
+                sqr: 2,
+                curl: 3,
+                angl: 4,
+            },
+            base: 5,
+            completion_agg: Aggregate::Median,
         }
     }
+}
 
-    e_paren * 3 + e_ang * 25137 + e_sqr * 57 + e_curl * 1197
+impl Scoring {
+    fn corrupted_score(&self, d: &Diagnostic) -> Option<usize> {
+        match d {
+            Diagnostic::Illegal(_, bc) | Diagnostic::Unopened(bc) => {
+                Some(self.corrupted.get(bc.kind))
+            }
+            _ => None,
+        }
+    }
+
+    // Saturates rather than overflowing on very long completions
+    fn completion_score(&self, v: &[BraceKind]) -> usize {
+        v.iter().fold(0, |acc: usize, &b| {
+            acc.saturating_mul(self.base)
+                .saturating_add(self.completion.get(b))
+        })
+    }
 }
 
-fn find_errs(v: &[char]) -> Result<(), ErrorKind> {
-    use Brace::*;
-    use BraceKind::*;
-    let mut vd = VecDeque::new();
-    for c in v {
-        match Brace::from(*c) {
-            Open(bo) => vd.push_back(bo),
-            Close(bc) => {
-                if let Some(bo) = vd.pop_back() {
-                    if bo != bc {
-                        match bo {
-                            Paren => return Err(ErrorKind::Illegal(bo, bc)),
-                            Angl => return Err(ErrorKind::Illegal(bo, bc)),
-                            Sqr => return Err(ErrorKind::Illegal(bo, bc)),
-                            Curl => return Err(ErrorKind::Illegal(bo, bc)),
-                        }
-                    }
+// Semicolon separated `key=value` overrides of the default scoring, e.g.
+// "corrupted=1,2,3,4;corrupted-agg=max;completion=1,1,1,1;base=2;completion-agg=sum"
+impl FromStr for Scoring {
+    type Err = color_eyre::eyre::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut scoring = Scoring::default();
+        for kv in s.split(';').map(str::trim).filter(|kv| !kv.is_empty()) {
+            match kv.split_once('=') {
+                Some(("corrupted", v)) => scoring.corrupted = v.parse()?,
+                Some(("corrupted-agg", v)) => scoring.corrupted_agg = v.parse()?,
+                Some(("completion", v)) => scoring.completion = v.parse()?,
+                Some(("base", v)) => scoring.base = v.parse()?,
+                Some(("completion-agg", v)) => scoring.completion_agg = v.parse()?,
+                _ => return Err(eyre!("invalid scoring: {}", kv)),
+            }
+        }
+        Ok(scoring)
+    }
+}
+
+fn print_parts(input: &[Vec<char>], scoring: &Scoring) {
+    match part_one(input, scoring) {
+        Some(n) => println!("part one: {}", n),
+        None => println!("part one: no corrupted lines"),
+    }
+    match part_two(input, scoring) {
+        Some(n) => println!("part two: {}", n),
+        None => println!("part two: no incomplete lines"),
+    }
+}
+
+fn score(mut args: impl Iterator<Item = String>) -> Result<()> {
+    let mut scoring = Scoring::default();
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--scoring" => {
+                scoring = args
+                    .next()
+                    .ok_or_else(|| eyre!("--scoring requires a value"))?
+                    .parse()?
+            }
+            _ => return Err(eyre!("unknown argument: {}", arg)),
+        }
+    }
+
+    print_parts(&parse_input(), &scoring);
+    Ok(())
+}
+
+// Language style used by bracket-lint to skip string literals and comments
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum Lang {
+    Plain,
+    C,
+    Rust,
+    Python,
+}
+
+impl FromStr for Lang {
+    type Err = color_eyre::eyre::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" | "none" => Ok(Lang::Plain),
+            "c" => Ok(Lang::C),
+            "rust" => Ok(Lang::Rust),
+            "python" => Ok(Lang::Python),
+            _ => Err(eyre!("invalid language style: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum LintError {
+    // Opener it was compared to, Found
+    Mismatched(BraceAt, BraceAt),
+    Unopened(BraceAt),
+    Unclosed(BraceAt),
+}
+
+impl LintError {
+    // Formats the error compiler style, with a caret under the offending brace
+    fn render(&self, path: &str, src: &str) -> String {
+        let (at, msg, note) = match self {
+            LintError::Mismatched(bo, bc) => {
+                let (line, col) = line_col(src, bo.offset);
+                (
+                    bc,
+                    format!("mismatched closing delimiter `{}`", bc.kind.close_char()),
+                    format!(
+                        "expected `{}` to close `{}` at {}:{}",
+                        bo.kind.close_char(),
+                        bo.kind.open_char(),
+                        line,
+                        col
+                    ),
+                )
+            }
+            LintError::Unopened(bc) => (
+                bc,
+                format!("unexpected closing delimiter `{}`", bc.kind.close_char()),
+                "nothing is open here".to_string(),
+            ),
+            LintError::Unclosed(bo) => (
+                bo,
+                format!("unclosed delimiter `{}`", bo.kind.open_char()),
+                "never closed".to_string(),
+            ),
+        };
+
+        let (line, col) = line_col(src, at.offset);
+        let text = src.lines().nth(line - 1).unwrap_or_default();
+        // Keep tabs so the caret lines up with the text above it
+        let pad: String = text
+            .chars()
+            .take(col - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let gutter = " ".repeat(line.to_string().len());
+        format!(
+            "error: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}^ {}\n",
+            msg, gutter, path, line, col, gutter, line, text, gutter, pad, note
+        )
+    }
+}
+
+// 1-based line and column (in chars) of a byte offset
+fn line_col(src: &str, offset: usize) -> (usize, usize) {
+    let before = &src[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+fn lint(src: &str, lang: Lang) -> Vec<LintError> {
+    let mut checker = Checker::default();
+    let mut errs = Vec::new();
+    for (offset, b) in code_braces(src, lang) {
+        match checker.feed(b, offset) {
+            Some(Diagnostic::Illegal(bo, bc)) => {
+                errs.push(LintError::Mismatched(bo, bc));
+                // Recover like a compiler would: if the closer matches something further out
+                // everything in between was left unclosed, otherwise it's a stray closer
+                match checker.stack.iter().rposition(|o| o.kind == bc.kind) {
+                    Some(i) => checker.stack.truncate(i),
+                    None => checker.stack.push_back(bo),
+                }
+            }
+            Some(Diagnostic::Unopened(bc)) => errs.push(LintError::Unopened(bc)),
+            _ => (),
+        }
+    }
+    errs.extend(checker.stack.into_iter().map(LintError::Unclosed));
+    errs
+}
+
+// Finds the byte offset of every brace outside of string literals and comments. Angle
+// brackets only count in plain text, in code they're usually comparisons.
+fn code_braces(src: &str, lang: Lang) -> Vec<(usize, Brace)> {
+    use Lang::*;
+    let b = src.as_bytes();
+    let mut braces = Vec::new();
+    let mut i = 0;
+    while i < b.len() {
+        let rest = &b[i..];
+        i = match lang {
+            C | Rust if rest.starts_with(b"//") => skip_line(b, i),
+            C | Rust if rest.starts_with(b"/*") => skip_block_comment(b, i, lang == Rust),
+            Python if rest[0] == b'#' => skip_line(b, i),
+            Python if rest.starts_with(b"\"\"\"") || rest.starts_with(b"'''") => {
+                skip_until(b, i + 3, &rest[..3])
+            }
+            C | Python if rest[0] == b'"' || rest[0] == b'\'' => skip_quoted(b, i),
+            Rust if rest[0] == b'"' => skip_quoted(b, i),
+            Rust if rest[0] == b'\'' && is_char_literal(&src[i..]) => skip_quoted(b, i),
+            Rust if rest[0] == b'r' && (i == 0 || !is_ident(b[i - 1])) => {
+                skip_raw_string(b, i).unwrap_or(i + 1)
+            }
+            _ => {
+                match Brace::try_from(b[i] as char) {
+                    Ok(Brace::Open(BraceKind::Angl) | Brace::Close(BraceKind::Angl))
+                        if lang != Plain => {}
+                    Ok(brace) => braces.push((i, brace)),
+                    Err(_) => (),
                 }
+                i + 1
+            }
+        };
+    }
+    braces
+}
+
+fn is_ident(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
+
+// Rust's `'` is either a char literal or a lifetime
+fn is_char_literal(s: &str) -> bool {
+    let mut chars = s.chars().skip(1);
+    match chars.next() {
+        Some('\\') => true,
+        Some(_) => chars.next() == Some('\''),
+        None => false,
+    }
+}
+
+fn skip_line(b: &[u8], i: usize) -> usize {
+    b[i..]
+        .iter()
+        .position(|&c| c == b'\n')
+        .map_or(b.len(), |p| i + p)
+}
+
+fn skip_until(b: &[u8], i: usize, delim: &[u8]) -> usize {
+    let i = i.min(b.len());
+    b[i..]
+        .windows(delim.len())
+        .position(|w| w == delim)
+        .map_or(b.len(), |p| i + p + delim.len())
+}
+
+fn skip_quoted(b: &[u8], i: usize) -> usize {
+    let quote = b[i];
+    let mut j = i + 1;
+    while j < b.len() {
+        match b[j] {
+            b'\\' => j += 2,
+            c if c == quote => return j + 1,
+            _ => j += 1,
+        }
+    }
+    b.len()
+}
+
+fn skip_block_comment(b: &[u8], i: usize, nested: bool) -> usize {
+    let mut depth = 0;
+    let mut j = i;
+    while j < b.len() {
+        if b[j..].starts_with(b"/*") && (nested || depth == 0) {
+            depth += 1;
+            j += 2;
+        } else if b[j..].starts_with(b"*/") {
+            depth -= 1;
+            j += 2;
+            if depth == 0 {
+                return j;
+            }
+        } else {
+            j += 1;
+        }
+    }
+    b.len()
+}
+
+// Skips r"..." and r#"..."#, or None if this `r` doesn't start a raw string
+fn skip_raw_string(b: &[u8], i: usize) -> Option<usize> {
+    let hashes = b[i + 1..].iter().take_while(|&&c| c == b'#').count();
+    if b.get(i + 1 + hashes) != Some(&b'"') {
+        return None;
+    }
+    let mut end = vec![b'"'];
+    end.resize(hashes + 1, b'#');
+    Some(skip_until(b, i + 2 + hashes, &end))
+}
+
+fn bracket_lint(mut args: impl Iterator<Item = String>) -> Result<()> {
+    let mut lang = Lang::Plain;
+    let mut paths = Vec::new();
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--lang" => {
+                lang = args
+                    .next()
+                    .ok_or_else(|| eyre!("--lang requires a value"))?
+                    .parse()?
+            }
+            _ => paths.push(arg),
+        }
+    }
+
+    let mut files = Vec::new();
+    if paths.is_empty() {
+        let mut src = String::new();
+        io::stdin().lock().read_to_string(&mut src)?;
+        files.push(("<stdin>".to_string(), src));
+    }
+    for path in paths {
+        let src = fs::read_to_string(&path)?;
+        files.push((path, src));
+    }
+
+    let mut count = 0;
+    for (path, src) in &files {
+        for err in lint(src, lang) {
+            eprintln!("{}", err.render(path, src));
+            count += 1;
+        }
+    }
+
+    if count > 0 {
+        eprintln!("error: found {} bracket error(s)", count);
+        process::exit(1);
+    }
+    Ok(())
+}
+
+// A user defined set of open/close delimiter pairs, which can be more than one character
+#[derive(Debug, Clone)]
+struct DelimSet {
+    pairs: Vec<(String, String)>,
+    // Every delimiter with the brace it maps to, longest first so `begin` wins over `b`
+    by_len: Vec<(String, Brace<usize>)>,
+}
+
+impl DelimSet {
+    fn new(pairs: Vec<(String, String)>) -> Result<Self> {
+        let mut by_len = Vec::new();
+        for (i, (open, close)) in pairs.iter().enumerate() {
+            if open.is_empty() || close.is_empty() || open == close {
+                return Err(eyre!("invalid delimiter pair: {:?} {:?}", open, close));
+            }
+            by_len.push((open.clone(), Brace::Open(i)));
+            by_len.push((close.clone(), Brace::Close(i)));
+        }
+        by_len.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then(a.cmp(b)));
+        if let Some(w) = by_len.windows(2).find(|w| w[0].0 == w[1].0) {
+            return Err(eyre!("duplicate delimiter: {:?}", w[0].0));
+        }
+
+        Ok(Self { pairs, by_len })
+    }
+
+    fn standard() -> Self {
+        Self::new(
+            ["()", "[]", "{}", "<>"]
+                .iter()
+                .map(|p| (p[..1].to_string(), p[1..].to_string()))
+                .collect(),
+        )
+        .unwrap()
+    }
+
+    // Finds the longest delimiter starting at `offset` along with its length. Delimiters that
+    // start or end like a word must also do so in the line, so `end` won't match in `blend`.
+    fn match_at(&self, line: &str, offset: usize) -> Option<(Brace<usize>, usize)> {
+        let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+        let rest = &line[offset..];
+        self.by_len.iter().find_map(|(d, b)| {
+            if !rest.starts_with(d.as_str()) {
+                return None;
+            }
+            let before = line[..offset].chars().last();
+            let after = rest[d.len()..].chars().next();
+            let split_word = (is_word(d.chars().next()) && is_word(before))
+                || (is_word(d.chars().last()) && is_word(after));
+            (!split_word).then_some((*b, d.len()))
+        })
+    }
+
+    fn describe(&self, d: &Diagnostic<usize>) -> String {
+        match d {
+            Diagnostic::Valid => "valid".to_string(),
+            Diagnostic::Illegal(bo, bc) => format!(
+                "expected `{}` to close `{}` at {}, found `{}` at {}",
+                self.pairs[bo.kind].1,
+                self.pairs[bo.kind].0,
+                bo.offset,
+                self.pairs[bc.kind].1,
+                bc.offset
+            ),
+            Diagnostic::Unopened(bc) => {
+                format!(
+                    "unexpected `{}` at {}, nothing is open",
+                    self.pairs[bc.kind].1, bc.offset
+                )
             }
+            Diagnostic::Incomplete(v) => format!(
+                "incomplete, missing `{}`",
+                v.iter()
+                    .map(|&k| &*self.pairs[k].1)
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+            Diagnostic::Unknown(offset, c) => format!("unknown character {:?} at {}", c, offset),
         }
     }
+}
 
-    Err(ErrorKind::Incomplete(vd.into_iter().rev().collect()))
+// Pairs are whitespace separated, open then close, e.g. "( ) [ ] begin end"
+impl FromStr for DelimSet {
+    type Err = color_eyre::eyre::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<_> = s.split_ascii_whitespace().collect();
+        if parts.len() % 2 != 0 {
+            return Err(eyre!("delimiter without a pair: {}", s));
+        }
+        Self::new(
+            parts
+                .chunks(2)
+                .map(|p| (p[0].to_string(), p[1].to_string()))
+                .collect(),
+        )
+    }
 }
 
-fn part_two(input: &[Vec<char>]) -> usize {
-    let mut scores: Vec<_> = input
+// What to do with characters that aren't part of a delimiter. Whitespace is always skipped.
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum Other {
+    Skip,
+    Reject,
+}
+
+fn check_delims(line: &str, delims: &DelimSet, other: Other) -> Diagnostic<usize> {
+    let mut checker = Checker::default();
+    let mut offset = 0;
+    while offset < line.len() {
+        if let Some((b, len)) = delims.match_at(line, offset) {
+            if let Some(d) = checker.feed(b, offset) {
+                return d;
+            }
+            offset += len;
+            continue;
+        }
+
+        let c = line[offset..].chars().next().unwrap();
+        if other == Other::Reject && !c.is_whitespace() {
+            return Diagnostic::Unknown(offset, c);
+        }
+        offset += c.len_utf8();
+    }
+
+    checker.finish()
+}
+
+fn check(mut args: impl Iterator<Item = String>) -> Result<()> {
+    let mut delims = DelimSet::standard();
+    let mut other = Other::Skip;
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--delims" => {
+                delims = args
+                    .next()
+                    .ok_or_else(|| eyre!("--delims requires a value"))?
+                    .parse()?
+            }
+            "--reject-other" => other = Other::Reject,
+            _ => return Err(eyre!("unknown argument: {}", arg)),
+        }
+    }
+
+    for (i, line) in io::stdin().lock().lines().enumerate() {
+        let d = check_delims(&line?, &delims, other);
+        println!("{}: {}", i + 1, delims.describe(&d));
+    }
+    Ok(())
+}
+
+// A single edit to a line, offsets are bytes into the original line and inserts go before them
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum Edit {
+    Insert(usize, char),
+    Delete(usize, char),
+    Replace(usize, char, char),
+}
+
+impl fmt::Display for Edit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Edit::Insert(at, c) => write!(f, "insert `{}` at {}", c, at),
+            Edit::Delete(at, c) => write!(f, "delete `{}` at {}", c, at),
+            Edit::Replace(at, from, to) => write!(f, "replace `{}` with `{}` at {}", from, to, at),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Repair {
+    line: String,
+    edits: Vec<Edit>,
+}
+
+// How the cheapest repair of a span treats its first char
+#[derive(Debug, Copy, Clone)]
+enum Step {
+    // Not a brace, kept as is
+    Skip,
+    // A stray closer is dropped
+    Delete,
+    // An unclosed opener gets its closer inserted at the end of the span
+    Insert(BraceKind),
+    // Pair it with the brace at this index, replacing either side if needed
+    Pair(usize),
+}
+
+// Longest line `repair` takes on, which a release build repairs in about a tenth of a second
+const MAX_REPAIR_LEN: usize = 500;
+
+// Finds the fewest insertions, deletions and replacements that balance the line. Any
+// character that isn't a brace is left alone. This is an O(n^3) interval DP over every span
+// of the line with O(n^2) memory, so longer lines than `MAX_REPAIR_LEN` are an error.
+fn repair(v: &[char]) -> Result<Repair> {
+    let n = v.len();
+    if n > MAX_REPAIR_LEN {
+        return Err(eyre!(
+            "{} characters is too long to repair, the limit is {}",
+            n,
+            MAX_REPAIR_LEN
+        ));
+    }
+    let braces: Vec<_> = v.iter().map(|&c| Brace::try_from(c).ok()).collect();
+    let idx = |i: usize, j: usize| i * (n + 1) + j;
+    // cost[idx(i, j)] is the cheapest repair of v[i..j]
+    let mut cost = vec![0; (n + 1) * (n + 1)];
+    let mut steps = vec![Step::Skip; (n + 1) * (n + 1)];
+
+    for len in 1..=n {
+        for i in 0..=n - len {
+            let j = i + len;
+            let Some(first) = braces[i] else {
+                cost[idx(i, j)] = cost[idx(i + 1, j)];
+                continue;
+            };
+
+            let mut best = match first {
+                Brace::Open(kind) => (cost[idx(i + 1, j)] + 1, Step::Insert(kind)),
+                Brace::Close(_) => (cost[idx(i + 1, j)] + 1, Step::Delete),
+            };
+            for k in i + 1..j {
+                let Some(other) = braces[k] else {
+                    continue;
+                };
+                let c = pair_cost(first, other) + cost[idx(i + 1, k)] + cost[idx(k + 1, j)];
+                // Prefer using the braces already there over inserting new ones
+                if c <= best.0 {
+                    best = (c, Step::Pair(k));
+                }
+            }
+            cost[idx(i, j)] = best.0;
+            steps[idx(i, j)] = best.1;
+        }
+    }
+
+    let offsets: Vec<_> = v
         .iter()
-        .map(|v| find_errs(v))
-        .filter(|e| e.is_err())
-        .filter_map(|e| match e {
-            Err(ErrorKind::Incomplete(v)) => Some(incomplete_score(&v)),
-            _ => None,
+        .scan(0, |off, c| {
+            let at = *off;
+            *off += c.len_utf8();
+            Some(at)
         })
+        .chain(std::iter::once(v.iter().map(|c| c.len_utf8()).sum()))
         .collect();
+    let mut repaired = Repair {
+        line: String::with_capacity(n),
+        edits: Vec::with_capacity(cost[idx(0, n)]),
+    };
+    rebuild(v, &braces, &steps, &offsets, &mut repaired);
+    Ok(repaired)
+}
+
+fn pair_cost(open: Brace, close: Brace) -> usize {
+    use Brace::*;
+    match (open, close) {
+        (Open(a), Close(b)) if a == b => 0,
+        (Open(_), _) | (_, Close(_)) => 1,
+        (Close(_), Open(_)) => 2,
+    }
+}
 
-    scores.sort_unstable();
-    scores[scores.len() / 2]
+// What's left to write out while rebuilding a repair, in order from the top of the stack
+enum Todo {
+    // The span v[i..j]
+    Span(usize, usize),
+    // The closer inserted at the end of a span for an unclosed opener
+    Insert(usize, BraceKind),
+    // The brace at an index, replaced if it's not the char wanted
+    Emit(usize, char),
 }
 
-fn incomplete_score(v: &[BraceKind]) -> usize {
-    use BraceKind::*;
-    v.iter().fold(0, |mut acc, b| {
-        acc *= 5;
+// Follows the steps from the whole line down, with a stack rather than recursion so deeply
+// nested lines can't overflow the call stack
+fn rebuild(
+    v: &[char],
+    braces: &[Option<Brace>],
+    steps: &[Step],
+    offsets: &[usize],
+    out: &mut Repair,
+) {
+    let n = v.len();
+    let mut todo = vec![Todo::Span(0, n)];
+    while let Some(next) = todo.pop() {
+        let (i, j) = match next {
+            Todo::Span(i, j) if i < j => (i, j),
+            Todo::Span(..) => continue,
+            Todo::Insert(j, kind) => {
+                out.edits.push(Edit::Insert(offsets[j], kind.close_char()));
+                out.line.push(kind.close_char());
+                continue;
+            }
+            Todo::Emit(k, want) => {
+                emit(out, offsets[k], v[k], want);
+                continue;
+            }
+        };
+        match (steps[i * (n + 1) + j], braces[i]) {
+            (Step::Skip, _) | (_, None) => {
+                out.line.push(v[i]);
+                todo.push(Todo::Span(i + 1, j));
+            }
+            (Step::Delete, _) => {
+                out.edits.push(Edit::Delete(offsets[i], v[i]));
+                todo.push(Todo::Span(i + 1, j));
+            }
+            (Step::Insert(kind), _) => {
+                out.line.push(v[i]);
+                todo.push(Todo::Insert(j, kind));
+                todo.push(Todo::Span(i + 1, j));
+            }
+            (Step::Pair(k), Some(first)) => {
+                let kind = match (first, braces[k]) {
+                    (Brace::Open(a), _) => a,
+                    (_, Some(Brace::Close(b))) => b,
+                    (Brace::Close(a), _) => a,
+                };
+                emit(out, offsets[i], v[i], kind.open_char());
+                todo.push(Todo::Span(k + 1, j));
+                todo.push(Todo::Emit(k, kind.close_char()));
+                todo.push(Todo::Span(i + 1, k));
+            }
+        }
+    }
+}
+
+fn emit(out: &mut Repair, at: usize, found: char, want: char) {
+    if found != want {
+        out.edits.push(Edit::Replace(at, found, want));
+    }
+    out.line.push(want);
+}
+
+fn repair_lines() -> Result<()> {
+    for (i, line) in io::stdin().lock().lines().enumerate() {
+        let r = match repair(&line?.chars().collect::<Vec<_>>()) {
+            Ok(r) => r,
+            Err(e) => {
+                println!("{}: {}", i + 1, e);
+                continue;
+            }
+        };
+        println!("{}: {} edit(s): {}", i + 1, r.edits.len(), r.line);
+        for e in &r.edits {
+            println!("    {}", e);
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Diagnostic::Valid => write!(f, "valid"),
+            Diagnostic::Illegal(bo, bc) => write!(
+                f,
+                "corrupted, expected `{}` to close `{}` at {}, found `{}` at {}",
+                bo.kind.close_char(),
+                bo.kind.open_char(),
+                bo.offset,
+                bc.kind.close_char(),
+                bc.offset
+            ),
+            Diagnostic::Unopened(bc) => write!(
+                f,
+                "corrupted, unexpected `{}` at {}, nothing is open",
+                bc.kind.close_char(),
+                bc.offset
+            ),
+            Diagnostic::Incomplete(v) => write!(
+                f,
+                "incomplete, missing `{}`",
+                v.iter().map(|k| k.close_char()).collect::<String>()
+            ),
+            Diagnostic::Unknown(offset, c) => write!(f, "unknown character {:?} at {}", c, offset),
+        }
+    }
+}
+
+// Deep enough for any sensible line while keeping the stack to a few tens of megabytes
+const DEFAULT_MAX_DEPTH: usize = 1 << 20;
+
+// Checks lines from a reader as they arrive, yielding each line's diagnostic once its newline
+// is seen. Only the open braces of the current line are held on to, and once a line is known
+// to be corrupted the rest of it is skipped, so inputs far bigger than memory can be checked.
+struct Stream<R> {
+    reader: R,
+    checker: Checker,
+    // The current line's result, once it's known before the end of the line
+    found: Option<io::Result<Diagnostic>>,
+    offset: usize,
+    max_depth: usize,
+    done: bool,
+}
+
+impl<R: BufRead> Stream<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            checker: Checker::default(),
+            found: None,
+            offset: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            done: false,
+        }
+    }
+
+    // Lines nested deeper than this yield an error instead of growing the stack any further
+    fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    fn end_line(&mut self) -> io::Result<Diagnostic> {
+        self.offset = 0;
+        let checker = mem::take(&mut self.checker);
+        self.found.take().unwrap_or_else(|| Ok(checker.finish()))
+    }
+}
+
+impl<R: BufRead> Iterator for Stream<R> {
+    type Item = io::Result<Diagnostic>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let buf = match self.reader.fill_buf() {
+                Ok(buf) => buf,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            };
+            if buf.is_empty() {
+                self.done = true;
+                // A last line without a trailing newline
+                if self.offset > 0 {
+                    return Some(self.end_line());
+                }
+                return None;
+            }
+
+            let mut used = buf.len();
+            let mut ended = false;
+            for (i, &b) in buf.iter().enumerate() {
+                if b == b'\n' {
+                    used = i + 1;
+                    ended = true;
+                    break;
+                }
+                if self.found.is_none() && b != b'\r' {
+                    let c = if b.is_ascii() {
+                        b as char
+                    } else {
+                        char::REPLACEMENT_CHARACTER
+                    };
+                    self.found = match Brace::try_from(c) {
+                        Ok(brace) => self.checker.feed(brace, self.offset).map(Ok),
+                        Err(c) => Some(Ok(Diagnostic::Unknown(self.offset, c))),
+                    };
+                    if self.found.is_none() && self.checker.stack.len() > self.max_depth {
+                        self.found = Some(Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("braces nested deeper than {}", self.max_depth),
+                        )));
+                    }
+                }
+                self.offset += 1;
+            }
+            self.reader.consume(used);
+
+            if ended {
+                return Some(self.end_line());
+            }
+        }
+        None
+    }
+}
+
+fn stream(mut args: impl Iterator<Item = String>) -> Result<()> {
+    let mut max_depth = DEFAULT_MAX_DEPTH;
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--max-depth" => {
+                max_depth = args
+                    .next()
+                    .ok_or_else(|| eyre!("--max-depth requires a value"))?
+                    .parse()?
+            }
+            _ => return Err(eyre!("unknown argument: {}", arg)),
+        }
+    }
+
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    for (i, d) in Stream::new(stdin.lock()).max_depth(max_depth).enumerate() {
+        match d {
+            Ok(d) => writeln!(out, "{}: {}", i + 1, d)?,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => writeln!(out, "{}: {}", i + 1, e)?,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+// A single line's classification for the report
+#[derive(Debug, PartialEq)]
+struct LineReport {
+    line: usize,
+    status: &'static str,
+    // The offending character and its byte offset for a corrupted line
+    found: Option<(char, usize)>,
+    completion: Option<String>,
+    score: Option<usize>,
+    // Why a line couldn't be checked at all, such as nesting too deep
+    error: Option<String>,
+}
+
+impl LineReport {
+    fn error(line: usize, e: &io::Error) -> Self {
+        Self {
+            line,
+            status: "error",
+            found: None,
+            completion: None,
+            score: None,
+            error: Some(e.to_string()),
+        }
+    }
+
+    fn new(line: usize, d: &Diagnostic, scoring: &Scoring) -> Self {
+        let (status, found, completion) = match d {
+            Diagnostic::Valid => ("valid", None, None),
+            Diagnostic::Illegal(_, bc) | Diagnostic::Unopened(bc) => {
+                ("corrupted", Some((bc.kind.close_char(), bc.offset)), None)
+            }
+            Diagnostic::Incomplete(v) => (
+                "incomplete",
+                None,
+                Some(v.iter().map(|k| k.close_char()).collect()),
+            ),
+            Diagnostic::Unknown(offset, c) => ("invalid", Some((*c, *offset)), None),
+        };
+        let score = match d {
+            Diagnostic::Incomplete(v) => Some(scoring.completion_score(v)),
+            d => scoring.corrupted_score(d),
+        };
+
+        Self {
+            line,
+            status,
+            found,
+            completion,
+            score,
+            error: None,
+        }
+    }
+
+    fn table_row(&self) -> String {
+        let opt = |o: Option<String>| o.unwrap_or_default();
+        format!(
+            "{:>6}  {:<10}  {:>12}  {:>4}  {:>6}  {}",
+            self.line,
+            self.status,
+            opt(self.score.map(|s| s.to_string())),
+            opt(self.found.map(|(c, _)| c.to_string())),
+            opt(self.found.map(|(_, at)| at.to_string())),
+            opt(self.completion.clone().or_else(|| self.error.clone())),
+        )
+    }
+
+    fn json(&self) -> String {
+        let null = || "null".to_string();
+        format!(
+            r#"{{"line":{},"status":"{}","char":{},"position":{},"completion":{},"score":{},"error":{}}}"#,
+            self.line,
+            self.status,
+            self.found
+                .map_or_else(null, |(c, _)| json_str(&c.to_string())),
+            self.found.map_or_else(null, |(_, at)| at.to_string()),
+            self.completion.as_deref().map_or_else(null, json_str),
+            self.score.map_or_else(null, |s| s.to_string()),
+            self.error.as_deref().map_or_else(null, json_str),
+        )
+    }
+}
+
+fn json_str(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn report(mut args: impl Iterator<Item = String>) -> Result<()> {
+    let mut scoring = Scoring::default();
+    let mut json = false;
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--json" => json = true,
+            "--scoring" => {
+                scoring = args
+                    .next()
+                    .ok_or_else(|| eyre!("--scoring requires a value"))?
+                    .parse()?
+            }
+            _ => return Err(eyre!("unknown argument: {}", arg)),
+        }
+    }
+
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if json {
+        write!(out, "[")?;
+    } else {
+        writeln!(
+            out,
+            "{:>6}  {:<10}  {:>12}  {:>4}  {:>6}  completion or error",
+            "line", "status", "score", "char", "pos"
+        )?;
+    }
+    for (i, d) in Stream::new(stdin.lock()).enumerate() {
+        let row = match d {
+            Ok(d) => LineReport::new(i + 1, &d, &scoring),
+            // Only that line is skipped, like `stream` does
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => LineReport::error(i + 1, &e),
+            Err(e) => return Err(e.into()),
+        };
+        if json {
+            let sep = if i == 0 { "" } else { "," };
+            write!(out, "{}\n  {}", sep, row.json())?;
+        } else {
+            writeln!(out, "{}", row.table_row().trim_end())?;
+        }
+    }
+    if json {
+        writeln!(out, "\n]")?;
+    }
+    Ok(())
+}
+
+// A matched pair of braces and everything nested inside it
+#[derive(Debug, PartialEq, Clone)]
+struct Group {
+    kind: BraceKind,
+    // Byte offset of the opener, the closer may have been added by completing the line
+    offset: usize,
+    // Indexes into `Structure::groups`
+    children: Vec<usize>,
+}
+
+// The groups are kept flat rather than as a tree of boxes, so however deeply a line nests
+// nothing has to recurse to walk or drop them
+#[derive(Debug, PartialEq)]
+struct Structure {
+    // Every group, in the order they were opened
+    groups: Vec<Group>,
+    // Indexes of the top-level groups
+    top: Vec<usize>,
+    max_depth: usize,
+    // Pairs of each kind in `( [ { <` order
+    counts: [usize; 4],
+    // Nesting depth after each character of the line, braces or not, then after each brace it
+    // was completed with
+    profile: Vec<usize>,
+    completion: Vec<BraceKind>,
+}
+
+// Parses a valid or incomplete line into a tree of groups, completing it if needed. Anything
+// else is returned as the diagnostic that stopped it.
+fn analyze(v: &[char]) -> Result<Structure, Diagnostic> {
+    let completion = match find_errs(v) {
+        Diagnostic::Valid => Vec::new(),
+        Diagnostic::Incomplete(c) => c,
+        d => return Err(d),
+    };
+
+    // The groups still open, with the top-level groups at the bottom
+    let mut open: Vec<usize> = Vec::new();
+    let mut groups = Vec::new();
+    let mut top = Vec::new();
+    let mut max_depth = 0;
+    let mut counts = [0; 4];
+    let mut profile = Vec::with_capacity(v.len() + completion.len());
+    let mut offset = 0;
+    let braces = v
+        .iter()
+        .map(|&c| (Brace::try_from(c).ok(), c.len_utf8()))
+        .chain(completion.iter().map(|&k| (Some(Brace::Close(k)), 0)));
+    for (b, len) in braces {
         match b {
-            Paren => acc += 1,
-            Sqr => acc += 2,
-            Curl => acc += 3,
-            Angl => acc += 4,
+            Some(Brace::Open(kind)) => {
+                open.push(groups.len());
+                groups.push(Group {
+                    kind,
+                    offset,
+                    children: Vec::new(),
+                });
+                max_depth = max_depth.max(open.len());
+            }
+            Some(Brace::Close(_)) => {
+                // find_errs already made sure this closes the innermost group
+                let g = open.pop().unwrap();
+                let kind = groups[g].kind;
+                counts[BraceKind::ALL.iter().position(|&k| k == kind).unwrap()] += 1;
+                match open.last() {
+                    Some(&parent) => groups[parent].children.push(g),
+                    None => top.push(g),
+                }
+            }
+            None => (),
         }
-        acc
+        profile.push(open.len());
+        offset += len;
+    }
+
+    Ok(Structure {
+        groups,
+        top,
+        max_depth,
+        counts,
+        profile,
+        completion,
     })
 }
 
+impl Structure {
+    // One brace per line indented by depth, with empty groups kept on a single line
+    fn pretty(&self) -> String {
+        let mut out = String::new();
+        // Groups still to write, or to close once their children are written, last first
+        let mut todo: Vec<_> = self.top.iter().rev().map(|&g| (g, 0, false)).collect();
+        while let Some((g, depth, close)) = todo.pop() {
+            let Group { kind, children, .. } = &self.groups[g];
+            let indent = "  ".repeat(depth);
+            if close {
+                out.push_str(&format!("{}{}\n", indent, kind.close_char()));
+            } else if children.is_empty() {
+                out.push_str(&format!(
+                    "{}{}{}\n",
+                    indent,
+                    kind.open_char(),
+                    kind.close_char()
+                ));
+            } else {
+                out.push_str(&format!("{}{}\n", indent, kind.open_char()));
+                todo.push((g, depth, true));
+                todo.extend(children.iter().rev().map(|&c| (c, depth + 1, false)));
+            }
+        }
+        out
+    }
+}
+
+impl fmt::Display for Structure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "max depth {}, {} top-level group(s)",
+            self.max_depth,
+            self.top.len()
+        )?;
+        for (k, n) in BraceKind::ALL.iter().zip(self.counts) {
+            write!(f, ", {}{} {}", k.open_char(), k.close_char(), n)?;
+        }
+        if !self.completion.is_empty() {
+            let c: String = self.completion.iter().map(|k| k.close_char()).collect();
+            write!(f, " (completed with `{}`)", c)?;
+        }
+        Ok(())
+    }
+}
+
+fn structure(args: impl Iterator<Item = String>) -> Result<()> {
+    let mut tree = false;
+    let mut profile = false;
+    for arg in args {
+        match &*arg {
+            "--tree" => tree = true,
+            "--profile" => profile = true,
+            _ => return Err(eyre!("unknown argument: {}", arg)),
+        }
+    }
+
+    for (i, v) in parse_input().iter().enumerate() {
+        let s = match analyze(v) {
+            Ok(s) => s,
+            Err(d) => {
+                println!("{}: {}", i + 1, d);
+                continue;
+            }
+        };
+        println!("{}: {}", i + 1, s);
+        if profile {
+            let p: Vec<_> = s.profile.iter().map(|d| d.to_string()).collect();
+            println!("    profile: {}", p.join(" "));
+        }
+        if tree {
+            for l in s.pretty().lines() {
+                println!("    {}", l);
+            }
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -153,11 +1472,15 @@ mod test {
             .collect()
     }
 
+    fn at(offset: usize, kind: BraceKind) -> BraceAt {
+        BraceAt { offset, kind }
+    }
+
     #[test]
     fn missing_angled() {
         assert_eq!(
             find_errs(&"[<(<(<(<{}))><([]([]()".chars().collect::<Vec<_>>()),
-            Err(ErrorKind::Illegal(BraceKind::Angl, BraceKind::Paren))
+            Diagnostic::Illegal(at(7, BraceKind::Angl), at(10, BraceKind::Paren))
         );
     }
 
@@ -165,7 +1488,7 @@ mod test {
     fn missing_paren() {
         assert_eq!(
             find_errs(&"[{[{({}]{}}([{[{{{}}([]".chars().collect::<Vec<_>>()),
-            Err(ErrorKind::Illegal(BraceKind::Paren, BraceKind::Sqr))
+            Diagnostic::Illegal(at(4, BraceKind::Paren), at(7, BraceKind::Sqr))
         );
     }
 
@@ -173,42 +1496,354 @@ mod test {
     fn missing_sq() {
         assert_eq!(
             find_errs(&"{([(<{}[<>[]}>{[]{[(<()>".chars().collect::<Vec<_>>()),
-            Err(ErrorKind::Illegal(BraceKind::Sqr, BraceKind::Curl))
+            Diagnostic::Illegal(at(7, BraceKind::Sqr), at(12, BraceKind::Curl))
         );
         assert_eq!(
             find_errs(&"[[<[([]))<([[{}[[()]]]".chars().collect::<Vec<_>>()),
-            Err(ErrorKind::Illegal(BraceKind::Sqr, BraceKind::Paren))
+            Diagnostic::Illegal(at(3, BraceKind::Sqr), at(8, BraceKind::Paren))
         );
         assert_eq!(
             find_errs(&"<{([([[(<>()){}]>(<<{{".chars().collect::<Vec<_>>()),
-            Err(ErrorKind::Illegal(BraceKind::Sqr, BraceKind::Angl))
+            Diagnostic::Illegal(at(5, BraceKind::Sqr), at(16, BraceKind::Angl))
         );
     }
 
+    #[test]
+    fn unopened() {
+        assert_eq!(
+            find_errs(&"[]<>)".chars().collect::<Vec<_>>()),
+            Diagnostic::Unopened(at(4, BraceKind::Paren))
+        );
+    }
+
+    #[test]
+    fn valid() {
+        assert_eq!(
+            find_errs(&"([]){<>}".chars().collect::<Vec<_>>()),
+            Diagnostic::Valid
+        );
+        assert_eq!(find_errs(&[]), Diagnostic::Valid);
+    }
+
     #[test]
     fn complete() {
         assert_eq!(
             find_errs(&"[({(<(())[]>[[{[]{<()<>>".chars().collect::<Vec<_>>()),
-            Err(ErrorKind::Incomplete(
+            Diagnostic::Incomplete(
                 "}}]])})]"
                     .chars()
                     .map(|c| {
-                        match Brace::from(c) {
+                        match Brace::try_from(c).unwrap() {
                             Brace::Open(b) => b,
                             Brace::Close(b) => b,
                         }
                     })
                     .collect()
-            ))
+            )
+        );
+    }
+
+    #[test]
+    fn delims_words() {
+        let delims: DelimSet = "( ) begin end".parse().unwrap();
+        assert_eq!(
+            check_delims("begin x := (blend) end", &delims, Other::Skip),
+            Diagnostic::Valid
+        );
+        assert_eq!(
+            check_delims("begin (x end)", &delims, Other::Skip),
+            Diagnostic::Illegal(
+                BraceAt { offset: 6, kind: 0 },
+                BraceAt { offset: 9, kind: 1 }
+            )
+        );
+        assert_eq!(
+            check_delims("begin begin end", &delims, Other::Skip),
+            Diagnostic::Incomplete(vec![1])
+        );
+        assert_eq!(
+            check_delims("begin x end", &delims, Other::Reject),
+            Diagnostic::Unknown(6, 'x')
+        );
+    }
+
+    #[test]
+    fn delims_longest_match() {
+        let delims: DelimSet = "< > <% %>".parse().unwrap();
+        assert_eq!(
+            check_delims("<%<>%>", &delims, Other::Reject),
+            Diagnostic::Valid
+        );
+        assert!("( ) ( ]".parse::<DelimSet>().is_err());
+        assert!("| |".parse::<DelimSet>().is_err());
+        assert!("( ) [".parse::<DelimSet>().is_err());
+    }
+
+    #[test]
+    fn unknown_char() {
+        assert_eq!(
+            find_errs(&"(a)".chars().collect::<Vec<_>>()),
+            Diagnostic::Unknown(1, 'a')
         );
     }
 
+    #[test]
+    fn repair_small() {
+        let r = |s: &str| repair(&s.chars().collect::<Vec<_>>()).unwrap();
+        assert_eq!(
+            r("([])"),
+            Repair {
+                line: "([])".to_string(),
+                edits: vec![]
+            }
+        );
+        assert_eq!(
+            r("(]"),
+            Repair {
+                line: "()".to_string(),
+                edits: vec![Edit::Replace(1, ']', ')')]
+            }
+        );
+        assert_eq!(
+            r("[<>"),
+            Repair {
+                line: "[<>]".to_string(),
+                edits: vec![Edit::Insert(3, ']')]
+            }
+        );
+        assert_eq!(
+            r("())"),
+            Repair {
+                line: "()".to_string(),
+                edits: vec![Edit::Delete(1, ')')]
+            }
+        );
+        assert_eq!(r(")(").edits.len(), 2);
+        assert_eq!(r("a(b").line, "a(b)");
+
+        assert_eq!(r(&"(".repeat(40)).edits.len(), 20);
+        assert!(repair(&['('; MAX_REPAIR_LEN + 1]).is_err());
+    }
+
+    #[test]
+    fn repair_test_input() {
+        for line in parse_test_input() {
+            let r = repair(&line).unwrap();
+            assert_eq!(
+                find_errs(&r.line.chars().collect::<Vec<_>>()),
+                Diagnostic::Valid
+            );
+            match find_errs(&line) {
+                // Finishing the line is always an option, but never the only one
+                Diagnostic::Incomplete(v) => assert!(r.edits.len() <= v.len()),
+                d => assert!(matches!(d, Diagnostic::Illegal(..)) && !r.edits.is_empty()),
+            }
+        }
+    }
+
+    #[test]
+    fn stream_matches_find_errs() {
+        // A tiny buffer so lines are split across reads
+        let reader = io::BufReader::with_capacity(3, TEST_INPUT.as_bytes());
+        let streamed: Vec<_> = Stream::new(reader).map(|d| d.unwrap()).collect();
+        let expected: Vec<_> = parse_test_input().iter().map(|v| find_errs(v)).collect();
+        assert_eq!(streamed, expected);
+    }
+
+    #[test]
+    fn stream_line_endings() {
+        let streamed: Vec<_> = Stream::new("()\r\n(\n\n)a".as_bytes())
+            .map(|d| d.unwrap())
+            .collect();
+        assert_eq!(
+            streamed,
+            vec![
+                Diagnostic::Valid,
+                Diagnostic::Incomplete(vec![BraceKind::Paren]),
+                Diagnostic::Valid,
+                Diagnostic::Unopened(at(0, BraceKind::Paren)),
+            ]
+        );
+    }
+
+    #[test]
+    fn stream_max_depth() {
+        let mut s = Stream::new("(((\n(()\n".as_bytes()).max_depth(2);
+        assert!(s.next().unwrap().is_err());
+        assert_eq!(
+            s.next().unwrap().unwrap(),
+            Diagnostic::Incomplete(vec![BraceKind::Paren])
+        );
+        assert!(s.next().is_none());
+
+        // Bounded even when no depth is given
+        let deep = "(".repeat(DEFAULT_MAX_DEPTH + 1);
+        assert!(Stream::new(deep.as_bytes()).next().unwrap().is_err());
+    }
+
+    #[test]
+    fn lint_across_lines() {
+        let src = "fn main() {\n    foo(a, [b)\n}\n";
+        assert_eq!(
+            lint(src, Lang::Plain),
+            vec![LintError::Mismatched(
+                at(23, BraceKind::Sqr),
+                at(25, BraceKind::Paren)
+            )]
+        );
+        assert_eq!(line_col(src, 25), (2, 14));
+    }
+
+    #[test]
+    fn lint_skips_strings_and_comments() {
+        let src = r##"
+let s = "(}";
+let c = '[';
+let r = r#"{"#;
+// )
+/* ] /* } */ */
+fn f<'a>(x: &'a str) -> bool { 1 < 2 }
+"##;
+        assert_eq!(lint(src, Lang::Rust), vec![]);
+        assert_ne!(lint(src, Lang::Plain), vec![]);
+
+        let src = "x = '(' # )\ny = \"\"\"\n]\"\"\"\n";
+        assert_eq!(lint(src, Lang::Python), vec![]);
+        assert_eq!(
+            lint("int x = a[')'];\n/* { */ }", Lang::C),
+            vec![LintError::Unopened(at(24, BraceKind::Curl))]
+        );
+    }
+
+    #[test]
+    fn lint_render() {
+        let src = "a\n\t(b]\n";
+        let errs = lint(src, Lang::Plain);
+        assert_eq!(
+            errs[0].render("f.txt", src),
+            "\
+error: mismatched closing delimiter `]`
+ --> f.txt:2:4
+  |
+2 | \t(b]
+  | \t  ^ expected `)` to close `(` at 2:2
+"
+        );
+        assert_eq!(
+            lint("{(", Lang::Plain),
+            vec![
+                LintError::Unclosed(at(0, BraceKind::Curl)),
+                LintError::Unclosed(at(1, BraceKind::Paren))
+            ]
+        );
+    }
+
+    #[test]
+    fn custom_scoring() {
+        let scoring: Scoring = "corrupted=1,1,1,1; completion-agg=max; base=2"
+            .parse()
+            .unwrap();
+        assert_eq!(part_one(&parse_test_input(), &scoring), Some(5));
+        // "}}>}>))))" is the highest completion
+        assert_eq!(part_two(&parse_test_input(), &scoring), Some(1583));
+        assert!("base=x".parse::<Scoring>().is_err());
+        assert!("corrupted=1,2,3".parse::<Scoring>().is_err());
+    }
+
+    #[test]
+    fn empty_scores() {
+        let scoring = Scoring::default();
+        assert_eq!(part_one(&[], &scoring), Some(0));
+        assert_eq!(part_two(&[], &scoring), None);
+        assert_eq!(Aggregate::Max.apply(vec![]), None);
+        assert_eq!(Aggregate::Median.apply(vec![4, 1, 3, 2]), Some(2));
+        assert_eq!(Aggregate::Sum.apply(vec![usize::MAX, 1]), Some(usize::MAX));
+    }
+
+    #[test]
+    fn line_report() {
+        let scoring = Scoring::default();
+        let rows: Vec<_> = ["{([(<{}[<>[]}>{[]{[(<()>", "<{([{{}}[<[[[<>{}]]]>[]]", "()"]
+            .iter()
+            .enumerate()
+            .map(|(i, l)| {
+                LineReport::new(i + 1, &find_errs(&l.chars().collect::<Vec<_>>()), &scoring)
+            })
+            .collect();
+        assert_eq!(
+            rows[0].json(),
+            r#"{"line":1,"status":"corrupted","char":"}","position":12,"completion":null,"score":1197,"error":null}"#
+        );
+        assert_eq!(
+            rows[1].json(),
+            r#"{"line":2,"status":"incomplete","char":null,"position":null,"completion":"])}>","score":294,"error":null}"#
+        );
+        assert_eq!(rows[2].table_row().trim_end(), "     3  valid");
+
+        let e = io::Error::new(io::ErrorKind::InvalidData, "too deep");
+        let row = LineReport::error(4, &e);
+        let table = row.table_row();
+        assert!(table.starts_with("     4  error ") && table.ends_with("  too deep"));
+        assert_eq!(
+            row.json(),
+            r#"{"line":4,"status":"error","char":null,"position":null,"completion":null,"score":null,"error":"too deep"}"#
+        );
+        assert_eq!(json_str("\"a\\\n"), r#""\"a\\\u000a""#);
+    }
+
+    #[test]
+    fn structure() {
+        let s = analyze(&"([]{<>})[".chars().collect::<Vec<_>>()).unwrap();
+        assert_eq!(s.max_depth, 3);
+        assert_eq!(s.top.len(), 2);
+        assert_eq!(s.counts, [1, 2, 1, 1]);
+        assert_eq!(s.profile, [1, 2, 1, 2, 3, 2, 1, 0, 1, 0]);
+        assert_eq!(s.completion, [BraceKind::Sqr]);
+        let inner = s.groups[s.top[0]].children[1];
+        assert_eq!(s.groups[s.groups[inner].children[0]].offset, 4);
+        assert_eq!(
+            s.pretty(),
+            "\
+(
+  []
+  {
+    <>
+  }
+)
+[]
+"
+        );
+        assert_eq!(
+            s.to_string(),
+            "max depth 3, 2 top-level group(s), () 1, [] 2, {} 1, <> 1 (completed with `]`)"
+        );
+        assert!(analyze(&"(]".chars().collect::<Vec<_>>()).is_err());
+    }
+
+    #[test]
+    fn structure_deep() {
+        // Deep enough to overflow the stack if anything recursed, including dropping it
+        let s = analyze(&['('; 200_000]).unwrap();
+        assert_eq!((s.max_depth, s.top.len()), (200_000, 1));
+        assert_eq!(s.completion.len(), 200_000);
+        drop(s);
+
+        let s = analyze(&['['; 500]).unwrap();
+        assert_eq!(s.pretty().lines().count(), 999);
+    }
+
     #[test]
     fn p_one() {
-        assert_eq!(part_one(&*parse_test_input()), 26397);
+        assert_eq!(
+            part_one(&parse_test_input(), &Scoring::default()),
+            Some(26397)
+        );
     }
     #[test]
     fn p_two() {
-        assert_eq!(part_two(&*parse_test_input()), 288957);
+        assert_eq!(
+            part_two(&parse_test_input(), &Scoring::default()),
+            Some(288957)
+        );
     }
 }